@@ -0,0 +1,35 @@
+#[macro_use]
+extern crate criterion;
+extern crate golfscript;
+
+use criterion::{BenchmarkId, Criterion};
+use golfscript::{Engine, Interpreter};
+
+const PROGRAMS: &[(&str, &str)] = &[
+    ("map", "20000,{.*1+}%;"),
+    ("nested_map", "200,{100,{1$+}%;}%;"),
+    ("fold", "20000,{+}*;"),
+    ("each", "0 20000,{+}/;"),
+    ("do", "0{1+.20000<}do;"),
+    ("string_map", "\"abcdefghij\"2000*{)}%;"),
+];
+
+fn engines(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engines");
+    for &(name, program) in PROGRAMS {
+        for &engine in &[Engine::Tree, Engine::Bytecode] {
+            let id = BenchmarkId::new(name, format!("{:?}", engine));
+            group.bench_with_input(id, program, |b, program| {
+                b.iter(|| {
+                    let mut it = Interpreter::new();
+                    it.set_engine(engine);
+                    it.exec(program).unwrap().len()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, engines);
+criterion_main!(benches);
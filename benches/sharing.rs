@@ -0,0 +1,26 @@
+#[macro_use]
+extern crate criterion;
+extern crate golfscript;
+
+use criterion::{BenchmarkId, Criterion};
+use golfscript::Interpreter;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    ("dup_array", "[1000,100{.}*]"),
+    ("variable_array", "1000,:a;[100{a}*]"),
+    ("map_over_shared", "1000,:a;100,{;a}%"),
+    ("nested_blocks", "{{{1}}}:f;[1000{f}*]"),
+];
+
+fn sharing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sharing");
+    for &(name, program) in PROGRAMS {
+        group.bench_with_input(BenchmarkId::from_parameter(name), program, |b, program| {
+            b.iter(|| Interpreter::new().exec(program).unwrap().len())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sharing);
+criterion_main!(benches);
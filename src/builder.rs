@@ -0,0 +1,110 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use items::GSError;
+use stack::Stack;
+use {Engine, Interpreter, Limits, Snapshot};
+
+/// The GolfScript source defining `n`, `puts`, `p`, `and`, `or` and `xor`,
+/// as the reference interpreter does.
+pub const PRELUDE: &str = include_str!("prelude.gs");
+
+/// Configure an `Interpreter` before creating it.
+///
+/// By default the interpreter reads from stdin, writes to stdout and runs
+/// the standard `PRELUDE`.
+pub struct InterpreterBuilder {
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+    engine: Engine,
+    limits: Limits,
+    prelude: Option<Cow<'static, str>>,
+}
+
+impl Default for InterpreterBuilder {
+    fn default() -> Self {
+        InterpreterBuilder::new()
+    }
+}
+
+impl InterpreterBuilder {
+    pub fn new() -> Self {
+        InterpreterBuilder {
+            input: Box::new(io::stdin()),
+            output: Box::new(io::stdout()),
+            engine: Engine::Tree,
+            limits: Limits::default(),
+            prelude: Some(Cow::Borrowed(PRELUDE)),
+        }
+    }
+
+    /// Read the input of `run` from `input` and write everything printed to
+    /// `output`.
+    pub fn io<R, W>(mut self, input: R, output: W) -> Self
+    where
+        R: Read + 'static,
+        W: Write + 'static,
+    {
+        self.input = Box::new(input);
+        self.output = Box::new(output);
+        self
+    }
+
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Run `prelude` instead of the standard one when the interpreter is
+    /// created, `reset` going back to the variables it defines.
+    ///
+    /// Extending the standard prelude is done by appending to `PRELUDE`.
+    pub fn prelude<S: Into<Cow<'static, str>>>(mut self, prelude: S) -> Self {
+        self.prelude = Some(prelude.into());
+        self
+    }
+
+    /// Start without any predefined variable.
+    pub fn no_prelude(mut self) -> Self {
+        self.prelude = None;
+        self
+    }
+
+    /// Create the interpreter, failing if the prelude does.
+    ///
+    /// The prelude is run once here and is not bound by the limits.
+    pub fn build(self) -> Result<Interpreter, GSError> {
+        let mut it = Interpreter {
+            stack: Stack::new(),
+            variables: HashMap::new(),
+            shadowed: HashSet::new(),
+            literals: HashSet::new(),
+            natives: HashMap::new(),
+            engine: self.engine,
+            limits: self.limits,
+            transactional: true,
+            ops: 0,
+            depth: 0,
+            allocated: 0,
+            deadline: None,
+            input: self.input,
+            output: self.output,
+            rng: Box::new(StdRng::from_entropy()),
+            prelude: Snapshot::default(),
+        };
+        if let Some(prelude) = self.prelude {
+            it.load_prelude(&prelude)?;
+        }
+        it.prelude = Snapshot::take(&it);
+        Ok(it)
+    }
+}
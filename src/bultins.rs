@@ -2,49 +2,186 @@ extern crate itertools;
 extern crate rand;
 
 use itertools::Itertools;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{pow, Signed, ToPrimitive, Zero};
 use rand::Rng;
-use std::{char, iter, mem};
+use std::hash::Hash;
+use std::io::Write;
+use std::{char, mem};
 
-use items::{GSError, Item};
+use items::{Code, ErrorKind, GSError, IntoVec, Item};
+use std::rc::Rc;
 use Interpreter;
 use Item::*;
 
 type GSErr = Result<(), GSError>;
 
+// Approximate memory taken by an element of an array or block.
+pub(crate) const ITEM_SIZE: usize = mem::size_of::<Item>();
+
 // Coerce the specified items a similar type.
-fn coerce((x, y): (Item, Item)) -> (Item, Item) {
-    match (x, y) {
-        (x, y @ Block(_)) | (x @ Block(_), y) => (x.upcast_to_block(), y.upcast_to_block()),
+fn coerce((x, y): (Item, Item)) -> Option<(Item, Item)> {
+    Some(match (x, y) {
+        (x, y @ Block(_)) | (x @ Block(_), y) => (x.upcast_to_block()?, y.upcast_to_block()?),
 
-        (x, y @ Str(_)) | (x @ Str(_), y) => (x.upcast_to_string(), y.upcast_to_string()),
+        (x, y @ Str(_)) | (x @ Str(_), y) => (x.upcast_to_string()?, y.upcast_to_string()?),
 
-        (x, y @ Array(_)) | (x @ Array(_), y) => (x.upcast_to_array(), y.upcast_to_array()),
+        (x, y @ Array(_)) | (x @ Array(_), y) => (x.upcast_to_array()?, y.upcast_to_array()?),
 
         (x, y) => (x, y),
+    })
+}
+
+// Convert a number into a count or a length.
+fn to_usize(x: &BigInt) -> Result<usize, GSError> {
+    x.to_usize()
+        .ok_or_else(|| ErrorKind::NumberOutOfRange(x.clone()).into())
+}
+
+// The character code of `c`, as strings are seen by the operators that
+// iterate over them.
+pub(crate) fn char_code(c: char) -> Item {
+    Num((c as u32).into())
+}
+
+// Every `step`-th element of `items`, from the end if `step` is negative.
+fn step_by<T>(mut items: Vec<T>, step: &BigInt) -> Result<Vec<T>, GSError> {
+    let abs = to_usize(&step.abs())?;
+    if abs == 0 {
+        return Err(ErrorKind::ZeroStep.into());
+    }
+    if step.is_negative() {
+        items.reverse();
     }
+    Ok(items.into_iter().step_by(abs).collect())
+}
+
+// Resolve a possibly negative offset into a position in `0..=len`.
+fn offset(x: &BigInt, len: usize) -> usize {
+    let os = if x.is_negative() {
+        BigInt::from(len) + x
+    } else {
+        x.clone()
+    };
+    if os.is_negative() {
+        0
+    } else {
+        os.to_usize().map_or(len, |os| os.min(len))
+    }
+}
+
+// Resolve a possibly negative index into a valid position in `0..len`.
+fn index(x: &BigInt, len: usize) -> Option<usize> {
+    let os = if x.is_negative() {
+        BigInt::from(len) + x
+    } else {
+        x.clone()
+    };
+    os.to_usize().filter(|&i| i < len)
+}
+
+// Setwise union, elements keep the order of their first occurrence.
+fn set_or<T: Clone + Eq + Hash>(x: Vec<T>, y: Vec<T>) -> Vec<T> {
+    x.into_iter().chain(y).unique().collect()
+}
+
+// Setwise intersection.
+fn set_and<T: Clone + Eq + Hash>(x: Vec<T>, y: Vec<T>) -> Vec<T> {
+    // TODO: improuve performance using a set to check if el is in y
+    x.into_iter().filter(|el| y.contains(el)).unique().collect()
+}
+
+// Setwise symmetric difference.
+fn set_xor<T: Clone + Eq + Hash>(x: Vec<T>, y: Vec<T>) -> Vec<T> {
+    // TODO: improuve performance using sets for the checking
+    let mut only_x = x
+        .iter()
+        .unique()
+        .filter(|el| !y.contains(el))
+        .cloned()
+        .collect_vec();
+    let only_y = y
+        .into_iter()
+        .unique()
+        .filter(|el| !x.contains(el))
+        .collect_vec();
+    only_x.extend(only_y);
+    only_x
+}
+
+// Remove from `x` every element that appears in `y`.
+fn set_sub<T: PartialEq>(x: Vec<T>, y: Vec<T>) -> Vec<T> {
+    // TODO: improuve performance using a set to check if el is in y
+    x.into_iter().filter(|el| !y.contains(el)).collect()
 }
 
 impl Interpreter {
+    /// Build the error for `op` applied to operands of the given types,
+    /// listed from the bottom to the top of the stack.
+    pub(crate) fn type_error(&self, op: &str, types: &[&'static str]) -> GSError {
+        ErrorKind::TypeMismatch {
+            op: op.to_string(),
+            types: types.to_vec(),
+            depth: self.stack.len() + types.len(),
+        }
+        .into()
+    }
+
+    /// Coerce two popped values to a similar type, `x` being the top one.
+    fn coerce_for(&self, op: &str, (x, y): (Item, Item)) -> Result<(Item, Item), GSError> {
+        let types = [y.type_name(), x.type_name()];
+        coerce((x, y)).ok_or_else(|| self.type_error(op, &types))
+    }
+
+    /// Pop the top two values off the stack and coerce them to a similar type.
+    fn pop2_coerce(&mut self, op: &str) -> Result<(Item, Item), GSError> {
+        let pair = self.pop2()?;
+        self.coerce_for(op, pair)
+    }
+
+    /// Sort `items` by the values `block` leaves on the stack for each of them.
+    fn sort_by_block<T, F>(
+        &mut self,
+        block: &Code,
+        items: Vec<T>,
+        to_item: F,
+    ) -> Result<Vec<T>, GSError>
+    where
+        F: Fn(&T) -> Item,
+    {
+        let block = block.ops();
+        let mut keyed = Vec::with_capacity(items.len());
+        for item in items {
+            keyed.push((self.fun_call_with(&block, to_item(&item))?, item));
+        }
+        keyed.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(keyed.into_iter().map(|(_, item)| item).collect())
+    }
+
     /// +
     pub fn add(&mut self) -> GSErr {
-        match coerce(self.pop2()?) {
+        match self.pop2_coerce("+")? {
             (Num(x), Num(y)) => self.push(Num(x + y)),
 
-            (Str(x), Str(y)) => self.push(Str(y + &x)),
+            (Str(x), Str(y)) => {
+                self.allocate(x.len() + y.len(), 1)?;
+                self.push(Str(y + &x))
+            }
 
             (Array(x), Array(y)) => {
+                self.allocate(x.len() + y.len(), ITEM_SIZE)?;
                 let mut y = y.into_vec();
-                y.extend(x.into_vec().into_iter());
-                self.push(Array(y.into_boxed_slice()));
+                y.extend(x.into_vec());
+                self.push(Array(y.into()));
             }
 
             (Block(x), Block(y)) => {
-                let mut y = y.into_vec();
-                y.extend(x.into_vec().into_iter());
-                self.push(Block(y.into_boxed_slice()));
+                self.allocate(x.len() + y.len(), ITEM_SIZE)?;
+                self.push(Block(y.concat(x)));
             }
 
-            _ => unimplemented!(),
+            (x, y) => return Err(self.type_error("+", &[y.type_name(), x.type_name()])),
         }
 
         Ok(())
@@ -55,19 +192,23 @@ impl Interpreter {
         // Handle parsing of numbers which '-' is a unary operator
         // This should be done in the lexer, a number is negative if the
         // '-' symbol immediately precedes the value (special lexer case)
-        match coerce(self.pop2()?) {
+        match self.pop2_coerce("-")? {
             (Num(x), Num(y)) => self.push(Num(y - x)),
             (Array(x), Array(y)) => {
-                // TODO: improuve performance using a set to check if el is in x
-                self.push(Array(
-                    y.into_vec()
-                        .into_iter()
-                        .filter(|el| !x.contains(el))
-                        .collect_vec()
-                        .into_boxed_slice(),
-                ));
+                self.allocate(y.len(), ITEM_SIZE)?;
+                self.push(Array(set_sub(y.into_vec(), x.into_vec()).into()));
+            }
+            (Str(x), Str(y)) => {
+                self.allocate(y.len(), 1)?;
+                self.push(Str(set_sub(y.chars().collect(), x.chars().collect())
+                    .into_iter()
+                    .collect()));
             }
-            _ => unimplemented!(),
+            (Block(x), Block(y)) => {
+                self.allocate(y.len(), ITEM_SIZE)?;
+                self.push(Block(set_sub(y.into_vec(), x.into_vec()).into()));
+            }
+            (x, y) => return Err(self.type_error("-", &[y.type_name(), x.type_name()])),
         }
 
         Ok(())
@@ -76,9 +217,9 @@ impl Interpreter {
     /// !
     pub fn not(&mut self) -> GSErr {
         if self.pop()?.is_true() {
-            self.push(Num(0))
+            self.push(Num(0.into()))
         } else {
-            self.push(Num(1))
+            self.push(Num(1.into()))
         }
         Ok(())
     }
@@ -98,42 +239,43 @@ impl Interpreter {
     pub fn dollar(&mut self) -> GSErr {
         match self.pop()? {
             Num(x) => {
-                if x >= self.stack.len() as i64 || x < 0 {
-                    return Err(GSError::Runtime(
-                        "attempting to index beyond stack".to_string(),
-                    ));
-                }
+                let os = match x.to_usize() {
+                    Some(x) if x < self.stack.len() => self.stack.len() - x - 1,
+                    _ => return Err(ErrorKind::StackUnderflow.into()),
+                };
 
-                let os = self.stack.len() - x as usize - 1;
                 let value = self.stack[os].clone();
                 self.push(value);
             }
 
             Str(x) => {
+                self.allocate(x.len(), 1)?;
                 let mut buf: Vec<char> = x.chars().collect();
                 buf.sort();
                 self.push(Str(buf.into_iter().collect()))
             }
             Array(mut items) => {
-                items.sort();
+                self.allocate(items.len(), ITEM_SIZE)?;
+                Rc::make_mut(&mut items).sort();
                 self.push(Array(items));
             }
 
-            Block(ref block) => match self.pop()? {
-                Array(mut items) => {
-                    items.sort_by_cached_key(|a| self.fun_call_with(block, a.clone()).unwrap());
-                    self.push(Array(items));
+            Block(block) => match self.pop()? {
+                Array(items) => {
+                    self.allocate(items.len(), ITEM_SIZE)?;
+                    let items = self.sort_by_block(&block, items.into_vec(), Item::clone)?;
+                    self.push(Array(items.into()));
                 }
                 Str(val) => {
-                    let mut buf: Vec<char> = val.chars().collect();
-                    buf.sort_by_cached_key(|a| {
-                        self.fun_call_with(block, Str(a.to_string())).unwrap()
-                    });
+                    self.allocate(val.len(), 1)?;
+                    let buf = val.chars().collect_vec();
+                    let buf = self.sort_by_block(&block, buf, |&c| char_code(c))?;
                     self.push(Str(buf.into_iter().collect()))
                 }
-                _ => unimplemented!(),
+                x => return Err(self.type_error("$", &[x.type_name(), "block"])),
             },
-            _ => unimplemented!(),
+
+            x => return Err(self.type_error("$", &[x.type_name()])),
         }
 
         Ok(())
@@ -141,87 +283,98 @@ impl Interpreter {
 
     /// *
     pub fn mul(&mut self) -> GSErr {
-        match self.pop2()? {
+        let (x, y) = self.pop2()?;
+        let types = [y.type_name(), x.type_name()];
+        match (x, y) {
             // multiplication
             (Num(x), Num(y)) => self.push(Num(x * y)),
 
             // repeat on Str Array and Block
-            (Num(y), _) | (_, Num(y)) if y < 0 => {
-                return Err(GSError::Runtime(
-                    "repeat string value is negative".to_string(),
-                ));
+            (Num(y), _) | (_, Num(y)) if y.is_negative() => {
+                return Err(ErrorKind::NegativeRepeat.into());
             }
             (Num(y), Str(x)) | (Str(x), Num(y)) => {
-                self.push(Str(iter::repeat(x).take(y as usize).collect()))
+                let times = to_usize(&y)?;
+                self.allocate(x.len().saturating_mul(times), 1)?;
+                self.push(Str(x.repeat(times)));
             }
             (Num(y), Array(x)) | (Array(x), Num(y)) => {
+                let len = x.len().saturating_mul(to_usize(&y)?);
+                self.allocate(len, ITEM_SIZE)?;
                 self.push(Array(
-                    x.iter()
-                        .cloned()
-                        .cycle()
-                        .take(x.len() * y as usize)
-                        .collect_vec()
-                        .into_boxed_slice(),
+                    x.iter().cloned().cycle().take(len).collect_vec().into(),
                 ));
             }
             (Num(y), Block(x)) | (Block(x), Num(y)) => {
-                for _ in 0..y {
-                    self.exec_items(&x)?;
+                let x = x.ops();
+                for _ in 0..to_usize(&y)? {
+                    self.exec_ops(&x)?;
                 }
             }
 
             // join on Array and Str
             (Array(x), Str(y)) | (Str(y), Array(x)) => {
-                self.push(Str(x
-                    .into_vec()
-                    .into_iter()
-                    .filter_map(|el| {
-                        if let Str(val) = el.upcast_to_string() {
-                            Some(val)
-                        } else {
-                            None
-                        }
-                    })
-                    .join(y.as_str())));
+                let mut parts = Vec::with_capacity(x.len());
+                for el in x.into_vec() {
+                    match el.upcast_to_string() {
+                        Some(Str(val)) => parts.push(val),
+                        _ => return Err(self.type_error("*", &types)),
+                    }
+                }
+                let seps = y.len().saturating_mul(parts.len().saturating_sub(1));
+                self.allocate(parts.iter().map(String::len).sum::<usize>() + seps, 1)?;
+                self.push(Str(parts.join(y.as_str())));
             }
             (Array(y), Array(x)) => {
+                let len = x
+                    .iter()
+                    .map(|el| match el {
+                        Array(i) => i.len(),
+                        _ => 1,
+                    })
+                    .sum::<usize>();
+                let seps = y.len().saturating_mul(x.len().saturating_sub(1));
+                self.allocate(len + seps, ITEM_SIZE)?;
                 let mut items: Vec<Item> = Vec::new();
                 let mut x = x.into_vec().into_iter().peekable();
                 while let Some(el) = x.next() {
                     match el {
-                        Array(i) => items.extend(i.into_vec().into_iter()),
+                        Array(i) => items.extend(i.into_vec()),
                         el => items.push(el),
                     }
-                    if x.peek() != None {
+                    if x.peek().is_some() {
                         items.extend_from_slice(&y);
                     }
                 }
-                self.push(Array(items.into_boxed_slice()));
+                self.push(Array(items.into()));
             }
             (Str(y), Str(x)) => {
+                let seps = y.len().saturating_mul(x.chars().count().saturating_sub(1));
+                self.allocate(x.len() + seps, 1)?;
                 self.push(Str(x.chars().join(&y)));
             }
 
             // fold on Array and Str
             (Block(y), Array(x)) | (Array(x), Block(y)) => {
-                let x_len = x.len();
+                let (y, x_len) = (y.ops(), x.len());
                 for el in x.into_vec() {
                     self.push(el);
                 }
                 for _ in 1..x_len {
-                    self.exec_items(&y)?;
+                    self.exec_ops(&y)?;
                 }
             }
             (Block(y), Str(x)) | (Str(x), Block(y)) => {
+                let y = y.ops();
                 for el in x.chars() {
-                    self.push(Num(el as i64));
+                    self.push(char_code(el));
                 }
-                for _ in 1..x.len() {
-                    self.exec_items(&y)?;
+                for _ in 1..x.chars().count() {
+                    self.exec_ops(&y)?;
                 }
             }
 
-            _ => unimplemented!(),
+            _ => return Err(self.type_error("*", &types)),
         }
         Ok(())
     }
@@ -229,10 +382,14 @@ impl Interpreter {
     /// /
     pub fn div(&mut self) -> GSErr {
         match self.pop2()? {
-            (Num(y), Num(x)) => self.push(Num(x / y)),
+            (Num(y), _) if y.is_zero() => {
+                return Err(ErrorKind::DivisionByZero.into());
+            }
+            (Num(y), Num(x)) => self.push(Num(x.div_floor(&y))),
 
             // split Array
             (Array(y), Array(x)) => {
+                self.allocate(x.len(), ITEM_SIZE)?;
                 let mut yit = y.iter().cycle();
                 let (mut v_nomatch, mut v_match, mut items) = (Vec::new(), Vec::new(), Vec::new());
                 for el in x.into_vec() {
@@ -241,7 +398,7 @@ impl Interpreter {
                         Some(i) if *i == el => v_match.push(el),
                         // save elements that do not match with split pattern
                         Some(i) if *i != el => {
-                            v_nomatch.extend(mem::take(&mut v_match).into_iter());
+                            v_nomatch.extend(mem::take(&mut v_match));
                             v_nomatch.push(el);
                             yit = y.iter().cycle();
                         }
@@ -250,118 +407,138 @@ impl Interpreter {
                     // split the array, all the pattern match
                     if v_match.len() == y.len() {
                         v_match.clear();
-                        items.push(Array(mem::take(&mut v_nomatch).into_boxed_slice()));
+                        items.push(Array(mem::take(&mut v_nomatch).into()));
                     }
                 }
                 // add remaining elements as last Array
                 if !v_nomatch.is_empty() {
-                    items.push(Array(v_nomatch.into_boxed_slice()));
+                    items.push(Array(v_nomatch.into()));
                 }
-                self.push(Array(items.into_boxed_slice()));
+                self.push(Array(items.into()));
             }
 
             // split Str
             (Str(y), Str(x)) => {
+                self.allocate(x.len(), 1)?;
                 self.push(Array(
                     x.split(y.as_str())
                         .map(|s| Str(s.to_owned()))
                         .collect_vec()
-                        .into_boxed_slice(),
+                        .into(),
                 ));
             }
 
             // chunk Array
             (Num(y), Array(x)) => {
+                self.allocate(x.len(), ITEM_SIZE)?;
                 self.push(Array(
                     x.into_vec()
                         .into_iter()
-                        .chunks(y as usize)
+                        .chunks(to_usize(&y)?)
                         .into_iter()
-                        .map(|c| Array(c.collect_vec().into_boxed_slice()))
+                        .map(|c| Array(c.collect_vec().into()))
                         .collect_vec()
-                        .into_boxed_slice(),
+                        .into(),
                 ));
             }
 
-            // each Array
-            (Block(y), Array(x)) => {
+            // each Array and Str
+            (Block(y), Array(x)) | (Array(x), Block(y)) => {
+                let y = y.ops();
                 for el in x.into_vec() {
                     self.push(el);
-                    self.exec_items(&y)?;
+                    self.exec_ops(&y)?;
+                }
+            }
+            (Block(y), Str(x)) | (Str(x), Block(y)) => {
+                let y = y.ops();
+                for c in x.chars() {
+                    self.push(char_code(c));
+                    self.exec_ops(&y)?;
                 }
             }
 
             // unfold Block
             (Block(y), Block(x)) => {
+                let (x, y) = (x.ops(), y.ops());
                 let mut items = Vec::new();
                 loop {
                     self.dup()?;
-                    let check = self.fun_call(&x)?;
-                    match check.last().unwrap() {
-                        Num(n) if *n != 0 => {
-                            items.push(self.peek()?);
-                            self.exec_items(&y)?;
-                        }
-                        Num(_) => {
-                            self.pop()?;
-                            break;
-                        }
-                        e => {
-                            return Err(GSError::Runtime(format!(
-                                "expected number but found: {:?}",
-                                e
-                            )))
-                        }
+                    if self.fun_call(&x)?.last().is_some_and(Item::is_true) {
+                        self.allocate(1, ITEM_SIZE)?;
+                        items.push(self.peek()?.clone());
+                        self.exec_ops(&y)?;
+                    } else {
+                        self.pop()?;
+                        break;
                     }
                 }
-                self.push(Array(items.into_boxed_slice()));
+                self.push(Array(items.into()));
             }
 
-            _ => unimplemented!(),
+            (x, y) => return Err(self.type_error("/", &[y.type_name(), x.type_name()])),
         }
         Ok(())
     }
 
     /// %
     pub fn modulo(&mut self) -> GSErr {
-        match self.pop2()? {
-            (Num(y), Num(x)) => self.push(Num(x % y)),
+        let (x, y) = self.pop2()?;
+        let types = [y.type_name(), x.type_name()];
+        match (x, y) {
+            (Num(y), Num(_)) if y.is_zero() => {
+                return Err(ErrorKind::DivisionByZero.into());
+            }
+            (Num(y), Num(x)) => self.push(Num(x.mod_floor(&y))),
 
             (Str(y), Str(x)) => {
+                self.allocate(x.len(), 1)?;
                 self.push(Array(
                     x.split(y.as_str())
                         .filter(|s| !s.is_empty())
                         .map(|s| Str(s.to_owned()))
                         .collect_vec()
-                        .into_boxed_slice(),
+                        .into(),
                 ));
             }
 
             (Num(y), Array(x)) => {
-                let mut items_vec = x
-                    .into_vec()
-                    .into_iter()
-                    .enumerate()
-                    .filter(|(i, _)| i % y.abs() as usize == 0)
-                    .map(|(_, val)| val)
-                    .collect_vec();
-                if y < 0 {
-                    items_vec.reverse();
-                }
-                self.push(Array(items_vec.into_boxed_slice()));
+                self.allocate(x.len(), ITEM_SIZE)?;
+                let items = step_by(x.into_vec(), &y)?;
+                self.push(Array(items.into()));
+            }
+            (Num(y), Str(x)) => {
+                self.allocate(x.len(), 1)?;
+                let chars = step_by(x.chars().collect(), &y)?;
+                self.push(Str(chars.into_iter().collect()));
             }
 
-            (Block(y), Array(x)) => {
-                let items = x
-                    .into_vec()
-                    .into_iter()
-                    .flat_map(|c| self.fun_call_with(&y, c).unwrap().into_iter())
-                    .collect_vec()
-                    .into_boxed_slice();
-                self.push(Array(items));
+            // map Array and Str
+            (Block(y), Array(x)) | (Array(x), Block(y)) => {
+                let y = y.ops();
+                let mut items = Vec::new();
+                for el in x.into_vec() {
+                    let res = self.fun_call_with(&y, el)?;
+                    self.allocate(res.len(), ITEM_SIZE)?;
+                    items.extend(res);
+                }
+                self.push(Array(items.into()));
+            }
+            (Block(y), Str(x)) | (Str(x), Block(y)) => {
+                let y = y.ops();
+                let mut items = Vec::new();
+                for c in x.chars() {
+                    let res = self.fun_call_with(&y, char_code(c))?;
+                    self.allocate(res.len(), ITEM_SIZE)?;
+                    items.extend(res);
+                }
+                match Array(items.into()).upcast_to_string() {
+                    Some(res) => self.push(res),
+                    None => return Err(self.type_error("%", &types)),
+                }
             }
 
-            _ => unimplemented!(),
+            _ => return Err(self.type_error("%", &types)),
         }
         Ok(())
     }
@@ -369,7 +546,7 @@ impl Interpreter {
     /// ~
     pub fn neg(&mut self) -> GSErr {
         match self.pop()? {
-            Num(x) => self.push(Num(!x)),
+            Num(x) => self.push(Num(-x - 1)),
 
             Array(x) => {
                 for item in x.into_vec() {
@@ -378,24 +555,18 @@ impl Interpreter {
             }
 
             Str(ref x) => {
-                if let Err(e) = self.exec(x) {
-                    return Err(GSError::Runtime(format!(
-                        "invalid expression statement: {:?}",
-                        e
-                    )));
-                }
+                // evaluating a string nests like running a block
+                self.enter()?;
+                let res = self.exec_source(x);
+                self.depth -= 1;
+                res?;
             }
 
             Block(ref x) => {
-                if let Err(e) = self.exec_items(x) {
-                    return Err(GSError::Runtime(format!(
-                        "invalid expression statement: {:?}",
-                        e
-                    )));
-                }
+                self.exec_block(x)?;
             }
 
-            _ => unimplemented!(),
+            x => return Err(self.type_error("~", &[x.type_name()])),
         }
         Ok(())
     }
@@ -409,71 +580,81 @@ impl Interpreter {
 
     /// |
     pub fn or(&mut self) -> GSErr {
-        match coerce(self.pop2()?) {
+        match self.pop2_coerce("|")? {
             (Num(y), Num(x)) => self.push(Num(x | y)),
 
             (Array(y), Array(x)) => {
-                self.push(Array(
-                    x.into_vec()
-                        .into_iter()
-                        .chain(y.into_vec().into_iter())
-                        .unique()
-                        .collect_vec()
-                        .into_boxed_slice(),
-                ));
+                self.allocate(x.len() + y.len(), ITEM_SIZE)?;
+                self.push(Array(set_or(x.into_vec(), y.into_vec()).into()));
+            }
+
+            (Str(y), Str(x)) => {
+                self.allocate(x.len() + y.len(), 1)?;
+                self.push(Str(set_or(x.chars().collect(), y.chars().collect())
+                    .into_iter()
+                    .collect()));
             }
 
-            _ => unimplemented!(),
+            (Block(y), Block(x)) => {
+                self.allocate(x.len() + y.len(), ITEM_SIZE)?;
+                self.push(Block(set_or(x.into_vec(), y.into_vec()).into()));
+            }
+
+            (y, x) => return Err(self.type_error("|", &[x.type_name(), y.type_name()])),
         }
         Ok(())
     }
 
     /// &
     pub fn and(&mut self) -> GSErr {
-        match coerce(self.pop2()?) {
+        match self.pop2_coerce("&")? {
             (Num(y), Num(x)) => self.push(Num(x & y)),
 
             (Array(y), Array(x)) => {
-                // TODO: improuve performance using a set to check if el is in y
-                self.push(Array(
-                    x.into_vec()
-                        .into_iter()
-                        .filter(|el| y.contains(el))
-                        .unique()
-                        .collect_vec()
-                        .into_boxed_slice(),
-                ));
+                self.allocate(x.len() + y.len(), ITEM_SIZE)?;
+                self.push(Array(set_and(x.into_vec(), y.into_vec()).into()));
+            }
+
+            (Str(y), Str(x)) => {
+                self.allocate(x.len() + y.len(), 1)?;
+                self.push(Str(set_and(x.chars().collect(), y.chars().collect())
+                    .into_iter()
+                    .collect()));
+            }
+
+            (Block(y), Block(x)) => {
+                self.allocate(x.len() + y.len(), ITEM_SIZE)?;
+                self.push(Block(set_and(x.into_vec(), y.into_vec()).into()));
             }
 
-            _ => unimplemented!(),
+            (y, x) => return Err(self.type_error("&", &[x.type_name(), y.type_name()])),
         }
         Ok(())
     }
 
     /// ^
     pub fn xor(&mut self) -> GSErr {
-        match coerce(self.pop2()?) {
+        match self.pop2_coerce("^")? {
             (Num(y), Num(x)) => self.push(Num(x ^ y)),
 
             (Array(y), Array(x)) => {
-                // TODO: improuve performance using sets for the checking
-                let mut only_x = x
-                    .iter()
-                    .unique()
-                    .filter(|el| !y.contains(el))
-                    .cloned()
-                    .collect_vec();
-                let only_y = y
-                    .into_vec()
+                self.allocate(x.len() + y.len(), ITEM_SIZE)?;
+                self.push(Array(set_xor(x.into_vec(), y.into_vec()).into()));
+            }
+
+            (Str(y), Str(x)) => {
+                self.allocate(x.len() + y.len(), 1)?;
+                self.push(Str(set_xor(x.chars().collect(), y.chars().collect())
                     .into_iter()
-                    .unique()
-                    .filter(|el| !x.contains(el))
-                    .collect_vec();
-                only_x.extend(only_y);
-                self.push(Array(only_x.into_boxed_slice()));
+                    .collect()));
             }
 
-            _ => unimplemented!(),
+            (Block(y), Block(x)) => {
+                self.allocate(x.len() + y.len(), ITEM_SIZE)?;
+                self.push(Block(set_xor(x.into_vec(), y.into_vec()).into()));
+            }
+
+            (y, x) => return Err(self.type_error("^", &[x.type_name(), y.type_name()])),
         }
         Ok(())
     }
@@ -495,30 +676,29 @@ impl Interpreter {
     // <
     pub fn lt(&mut self) -> GSErr {
         match self.pop2()? {
-            (y @ Num(_), x @ Num(_)) | (y @ Str(_), x @ Str(_)) => {
-                self.push(Num(if x < y { 1 } else { 0 }));
-            }
-
             (Num(x), Array(y)) | (Array(y), Num(x)) => {
-                let y_len = y.len() as i64;
+                let os = offset(&x, y.len());
+                self.allocate(os, ITEM_SIZE)?;
                 self.push(Array(
-                    y.into_vec()
-                        .into_iter()
-                        .take(if x > 0 { x } else { y_len + x } as usize)
-                        .collect_vec()
-                        .into_boxed_slice(),
+                    y.into_vec().into_iter().take(os).collect_vec().into(),
                 ));
             }
 
             (Num(x), Str(y)) | (Str(y), Num(x)) => {
-                let y_len = y.len() as i64;
-                self.push(Str(y
-                    .chars()
-                    .take(if x > 0 { x } else { y_len + x } as usize)
-                    .collect()));
+                let os = offset(&x, y.chars().count());
+                self.allocate(y.len(), 1)?;
+                self.push(Str(y.chars().take(os).collect()));
             }
 
-            _ => unimplemented!(),
+            // TODO: Block internals should be treated as a string
+            (y @ Num(_), x @ Block(_)) | (y @ Block(_), x @ Num(_)) => {
+                return Err(self.type_error("<", &[x.type_name(), y.type_name()]));
+            }
+
+            pair => {
+                let (y, x) = self.coerce_for("<", pair)?;
+                self.push(Num(if x < y { 1 } else { 0 }.into()));
+            }
         }
 
         Ok(())
@@ -527,30 +707,29 @@ impl Interpreter {
     // >
     pub fn gt(&mut self) -> GSErr {
         match self.pop2()? {
-            (y @ Num(_), x @ Num(_)) | (y @ Str(_), x @ Str(_)) => {
-                self.push(Num(if x > y { 1 } else { 0 }));
-            }
-
             (Num(x), Str(y)) | (Str(y), Num(x)) => {
-                let y_len = y.len() as i64;
-                self.push(Str(y
-                    .chars()
-                    .skip(if x > 0 { x } else { y_len + x } as usize)
-                    .collect()));
+                let os = offset(&x, y.chars().count());
+                self.allocate(y.len(), 1)?;
+                self.push(Str(y.chars().skip(os).collect()));
             }
 
             (Num(x), Array(y)) | (Array(y), Num(x)) => {
-                let y_len = y.len() as i64;
+                let os = offset(&x, y.len());
+                self.allocate(y.len() - os, ITEM_SIZE)?;
                 self.push(Array(
-                    y.into_vec()
-                        .into_iter()
-                        .skip(if x > 0 { x } else { y_len + x } as usize)
-                        .collect_vec()
-                        .into_boxed_slice(),
+                    y.into_vec().into_iter().skip(os).collect_vec().into(),
                 ));
             }
 
-            _ => unimplemented!(),
+            // TODO: Block internals should be treated as a string
+            (y @ Num(_), x @ Block(_)) | (y @ Block(_), x @ Num(_)) => {
+                return Err(self.type_error(">", &[x.type_name(), y.type_name()]));
+            }
+
+            pair => {
+                let (y, x) = self.coerce_for(">", pair)?;
+                self.push(Num(if x > y { 1 } else { 0 }.into()));
+            }
         }
 
         Ok(())
@@ -559,30 +738,28 @@ impl Interpreter {
     // =
     pub fn eq(&mut self) -> GSErr {
         match self.pop2()? {
-            (y @ Num(_), x @ Num(_))
-            | (y @ Array(_), x @ Array(_))
-            | (y @ Str(_), x @ Str(_))
-            | (y @ Block(_), x @ Block(_)) => {
-                self.push(Num(if x == y { 1 } else { 0 }));
-            }
-
             (Num(x), Array(y)) | (Array(y), Num(x)) => {
-                let os = if x < 0 { y.len() as i64 + x } else { x };
-
-                if let Some(el) = y.get(os as usize) {
-                    self.push(el.clone());
+                if let Some(os) = index(&x, y.len()) {
+                    self.push(y[os].clone());
                 }
             }
 
             (Num(x), Str(y)) | (Str(y), Num(x)) => {
-                let os = if x < 0 { y.len() as i64 + x } else { x };
-
-                if let Some(el) = y.chars().nth(os as usize) {
-                    self.push(Num(el as i64));
+                let el = index(&x, y.chars().count()).and_then(|os| y.chars().nth(os));
+                if let Some(el) = el {
+                    self.push(Num((el as u32).into()));
                 }
             }
 
-            _ => unimplemented!(),
+            // TODO: Block internals should be treated as a string
+            (y @ Num(_), x @ Block(_)) | (y @ Block(_), x @ Num(_)) => {
+                return Err(self.type_error("=", &[x.type_name(), y.type_name()]));
+            }
+
+            pair => {
+                let (y, x) = self.coerce_for("=", pair)?;
+                self.push(Num(if x == y { 1 } else { 0 }.into()));
+            }
         }
 
         Ok(())
@@ -592,33 +769,48 @@ impl Interpreter {
     pub fn array(&mut self) -> GSErr {
         match self.pop()? {
             Num(x) => {
-                self.push(Array((0..x).map(Num).collect_vec().into_boxed_slice()));
+                let len = if x.is_positive() { to_usize(&x)? } else { 0 };
+                self.allocate(len, ITEM_SIZE)?;
+                self.push(Array((0..len).map(|i| Num(i.into())).collect_vec().into()));
             }
 
             Array(x) => {
-                self.push(Num(x.len() as i64));
+                self.push(Num(x.len().into()));
+            }
+
+            Str(x) => {
+                self.push(Num(x.chars().count().into()));
             }
 
             Block(y) => match self.pop()? {
                 Array(x) => {
-                    let items = x
-                        .into_vec()
-                        .into_iter()
-                        .filter(|el| {
-                            self.fun_call_with(&y, el.clone())
-                                .unwrap()
-                                .last()
-                                .unwrap()
-                                .is_true()
-                        })
-                        .collect_vec();
-                    self.push(Array(items.into_boxed_slice()));
-                }
-
-                _ => unimplemented!(),
+                    self.allocate(x.len(), ITEM_SIZE)?;
+                    let y = y.ops();
+                    let mut items = Vec::new();
+                    for el in x.into_vec() {
+                        if self.test_with(&y, el.clone())? {
+                            items.push(el);
+                        }
+                    }
+                    self.push(Array(items.into()));
+                }
+
+                Str(x) => {
+                    self.allocate(x.len(), 1)?;
+                    let y = y.ops();
+                    let mut buf = String::new();
+                    for c in x.chars() {
+                        if self.test_with(&y, char_code(c))? {
+                            buf.push(c);
+                        }
+                    }
+                    self.push(Str(buf));
+                }
+
+                x => return Err(self.type_error(",", &[x.type_name(), "block"])),
             },
 
-            _ => unimplemented!(),
+            x => return Err(self.type_error(",", &[x.type_name()])),
         }
         Ok(())
     }
@@ -635,36 +827,52 @@ impl Interpreter {
     pub fn qmark(&mut self) -> GSErr {
         match self.pop2()? {
             (Num(y), Num(x)) => {
-                if y < 0 {
-                    return Err(GSError::Runtime(
-                        "cannot raise to negative power".to_string(),
-                    ));
+                if y.is_negative() {
+                    return Err(ErrorKind::NegativePower.into());
                 }
 
-                // Handle overflow somehow (may have to use own power)
-                self.push(Num(x.pow(y as u32)))
+                let y = to_usize(&y)?;
+                if x.bits() > 1 {
+                    self.allocate(x.bits().saturating_mul(y) / 8, 1)?;
+                }
+                self.push(Num(pow(x, y)))
             }
 
-            (Array(y), Num(x)) => {
-                self.push(Num(y
-                    .iter()
-                    .position(|v| v == &Num(x))
-                    .map_or_else(|| -1, |x| x as i64)));
+            // find Array and Str
+            (Block(y), Array(x)) | (Array(x), Block(y)) => {
+                let y = y.ops();
+                for el in x.into_vec() {
+                    if self.test_with(&y, el.clone())? {
+                        self.push(el);
+                        break;
+                    }
+                }
             }
-
-            (Block(y), Array(x)) => {
-                if let Some(item) = x.into_vec().into_iter().find(|v| {
-                    self.fun_call_with(&y, v.clone())
-                        .unwrap()
-                        .last()
-                        .unwrap()
-                        .is_true()
-                }) {
-                    self.push(item);
+            (Block(y), Str(x)) | (Str(x), Block(y)) => {
+                let y = y.ops();
+                for c in x.chars() {
+                    if self.test_with(&y, char_code(c))? {
+                        self.push(char_code(c));
+                        break;
+                    }
                 }
             }
 
-            _ => unimplemented!(),
+            // index in Array and Str
+            (Str(y), Str(x)) => {
+                let pos = x.find(y.as_str()).map(|i| x[..i].chars().count());
+                self.push(Num(pos.map_or_else(|| (-1).into(), BigInt::from)));
+            }
+            (Num(y), Str(x)) | (Str(x), Num(y)) => {
+                let pos = x.chars().position(|c| char_code(c) == Num(y.clone()));
+                self.push(Num(pos.map_or_else(|| (-1).into(), BigInt::from)));
+            }
+            (Array(y), x) | (x, Array(y)) => {
+                let pos = y.iter().position(|v| v == &x);
+                self.push(Num(pos.map_or_else(|| (-1).into(), BigInt::from)));
+            }
+
+            (x, y) => return Err(self.type_error("?", &[y.type_name(), x.type_name()])),
         }
 
         Ok(())
@@ -679,12 +887,20 @@ impl Interpreter {
                 if !x.is_empty() {
                     let mut buf = x.into_vec();
                     let cons = buf.remove(0);
-                    self.push(Array(buf.into_boxed_slice()));
+                    self.push(Array(buf.into()));
                     self.push(cons);
                 }
             }
 
-            _ => unimplemented!(),
+            Str(x) => {
+                let mut chars = x.chars();
+                if let Some(cons) = chars.next() {
+                    self.push(Str(chars.collect()));
+                    self.push(char_code(cons));
+                }
+            }
+
+            x => return Err(self.type_error("(", &[x.type_name()])),
         }
 
         Ok(())
@@ -696,15 +912,21 @@ impl Interpreter {
             Num(x) => self.push(Num(x + 1)),
 
             Array(x) => {
-                if !x.is_empty() {
-                    let mut buf = x.into_vec();
-                    let uncons = buf.pop().unwrap();
-                    self.push(Array(buf.into_boxed_slice()));
+                let mut buf = x.into_vec();
+                if let Some(uncons) = buf.pop() {
+                    self.push(Array(buf.into()));
                     self.push(uncons);
                 }
             }
 
-            _ => unimplemented!(),
+            Str(mut x) => {
+                if let Some(uncons) = x.pop() {
+                    self.push(Str(x));
+                    self.push(char_code(uncons));
+                }
+            }
+
+            x => return Err(self.type_error(")", &[x.type_name()])),
         }
 
         Ok(())
@@ -712,18 +934,19 @@ impl Interpreter {
 
     // [
     pub fn marker(&mut self) -> GSErr {
-        self.marker_stack.push(self.stack.len());
+        let len = self.stack.len();
+        self.stack.markers.push(len);
         Ok(())
     }
 
     // ]
     pub fn slice(&mut self) -> GSErr {
-        let offset = match self.marker_stack.pop() {
+        let offset = match self.stack.markers.pop() {
             Some(value) => value,
-            None => return Err(GSError::Runtime("marker stack underflow".to_string())),
+            None => return Err(ErrorKind::MarkerUnderflow.into()),
         };
 
-        let array_items = self.stack.split_off(offset).into_boxed_slice();
+        let array_items = self.stack.items.split_off(offset).into();
         self.push(Array(array_items));
         Ok(())
     }
@@ -732,7 +955,7 @@ impl Interpreter {
     pub fn builtin_abs(&mut self) -> GSErr {
         match self.pop()? {
             Num(x) => self.push(Num(x.abs())),
-            x => return Err(GSError::Runtime(format!("invalid type for `abs`: {:?}", x))),
+            x => return Err(self.type_error("abs", &[x.type_name()])),
         }
         Ok(())
     }
@@ -748,40 +971,87 @@ impl Interpreter {
         match (y, x) {
             // if the branch is a block execute it, otherwise push it into the stack
             (_, Block(x)) if check => {
-                self.exec_items(&x)?;
+                self.exec_block(&x)?;
             }
             (Block(y), _) if !check => {
-                self.exec_items(&y)?;
+                self.exec_block(&y)?;
             }
             (y, x) => self.push(if check { x } else { y }),
         }
         Ok(())
     }
 
+    // do
+    pub fn builtin_do(&mut self) -> GSErr {
+        match self.pop()? {
+            Block(body) => {
+                let body = body.ops();
+                loop {
+                    // the condition is left on the stack by the body
+                    self.exec_ops(&body)?;
+                    if !self.pop()?.is_true() {
+                        break;
+                    }
+                }
+            }
+            x => return Err(self.type_error("do", &[x.type_name()])),
+        }
+        Ok(())
+    }
+
+    // while
+    pub fn builtin_while(&mut self) -> GSErr {
+        self.cond_loop("while", true)
+    }
+
+    // until
+    pub fn builtin_until(&mut self) -> GSErr {
+        self.cond_loop("until", false)
+    }
+
+    /// Run the body block while the condition block evaluates to `expected`.
+    fn cond_loop(&mut self, name: &str, expected: bool) -> GSErr {
+        match self.pop2()? {
+            (Block(body), Block(cond)) => {
+                let (body, cond) = (body.ops(), cond.ops());
+                loop {
+                    self.exec_ops(&cond)?;
+                    if self.pop()?.is_true() != expected {
+                        break;
+                    }
+                    self.exec_ops(&body)?;
+                }
+            }
+            (y, x) => return Err(self.type_error(name, &[x.type_name(), y.type_name()])),
+        }
+        Ok(())
+    }
+
     // rand
     pub fn builtin_rand(&mut self) -> GSErr {
         match self.pop()? {
             Num(x) => {
-                if x == 0 {
-                    return Err(GSError::Runtime("invalid random range: [0, 0)".to_string()));
-                }
-                let mut rng = rand::thread_rng();
+                let x = match x.to_i64() {
+                    Some(x) if x != 0 => x,
+                    _ => return Err(ErrorKind::InvalidRange(x).into()),
+                };
                 let n = if x < 0 {
-                    rng.gen_range(x, 0)
+                    self.rng.gen_range(x, 0)
                 } else {
-                    rng.gen_range(0, x)
+                    self.rng.gen_range(0, x)
                 };
-                self.push(Num(n));
+                self.push(Num(n.into()));
             }
 
-            x => panic!("invalid type for `rand`: {:?}", x),
+            x => return Err(self.type_error("rand", &[x.type_name()])),
         }
         Ok(())
     }
 
     // print
     pub fn builtin_print(&mut self) -> GSErr {
-        println!("{}", self.pop()?);
+        let item = self.pop()?;
+        write!(self.output, "{}", item.output())?;
         Ok(())
     }
 
@@ -793,131 +1063,118 @@ impl Interpreter {
 
     // zip
     pub fn builtin_zip(&mut self) -> GSErr {
-        match self.pop()? {
-            Array(a) => {
-                let items = match a {
-                    // All the elements are Arrays
-                    a if a.iter().all(|el| match el {
-                        Array(_) => true,
-                        _ => false,
-                    }) =>
-                    {
-                        let mut res: Vec<Vec<Item>> = Vec::new();
-                        for b in a.into_vec().into_iter() {
-                            match b {
-                                Array(b) => {
-                                    for (j, x) in b.into_vec().into_iter().enumerate() {
-                                        if let Some(a) = res.get_mut(j) {
-                                            a.push(x);
-                                        } else {
-                                            res.push(vec![x]);
-                                        }
-                                    }
-                                }
-                                Str(_) => unimplemented!(),
-                                _ => unimplemented!(),
+        let x = self.pop()?;
+        if let Array(ref a) = x {
+            self.allocate(a.iter().map(Item::size).sum(), ITEM_SIZE)?;
+        }
+        let items = match x {
+            // All the elements are Arrays
+            Array(a) if a.iter().all(|el| matches!(el, Array(_))) => {
+                let mut res: Vec<Vec<Item>> = Vec::new();
+                for b in a.into_vec() {
+                    if let Array(b) = b {
+                        for (j, x) in b.into_vec().into_iter().enumerate() {
+                            if let Some(a) = res.get_mut(j) {
+                                a.push(x);
+                            } else {
+                                res.push(vec![x]);
                             }
                         }
-                        res.into_iter()
-                            .map(|a| Array(a.into_boxed_slice()))
-                            .collect_vec()
                     }
-                    // All the elements of are Str
-                    a if a.iter().all(|el| match el {
-                        Str(_) => true,
-                        _ => false,
-                    }) =>
-                    {
-                        let mut res: Vec<String> = Vec::new();
-                        for b in a.into_vec().into_iter() {
-                            match b {
-                                Str(b) => {
-                                    for (j, x) in b.chars().enumerate() {
-                                        if let Some(a) = res.get_mut(j) {
-                                            a.push(x);
-                                        } else {
-                                            res.push(x.to_string());
-                                        }
-                                    }
-                                }
-                                _ => unimplemented!(),
+                }
+                res.into_iter().map(|a| Array(a.into())).collect_vec()
+            }
+            // All the elements of are Str
+            Array(a) if a.iter().all(|el| matches!(el, Str(_))) => {
+                let mut res: Vec<String> = Vec::new();
+                for b in a.into_vec() {
+                    if let Str(b) = b {
+                        for (j, x) in b.chars().enumerate() {
+                            if let Some(a) = res.get_mut(j) {
+                                a.push(x);
+                            } else {
+                                res.push(x.to_string());
                             }
                         }
-                        res.into_iter().map(Str).collect_vec()
                     }
-                    _ => unimplemented!(),
-                };
-
-                // push the resulting Array
-                self.push(Array(items.into_boxed_slice()));
+                }
+                res.into_iter().map(Str).collect_vec()
             }
-            _ => unimplemented!(),
-        }
+            x => return Err(self.type_error("zip", &[x.type_name()])),
+        };
+
+        // push the resulting Array
+        self.push(Array(items.into()));
         Ok(())
     }
 
     pub fn builtin_base(&mut self) -> GSErr {
-        match self.pop2()? {
+        let (x, y) = self.pop2()?;
+        let types = [y.type_name(), x.type_name()];
+        match (x, y) {
             (Num(radix), Array(x)) => {
                 // converto x from `radix` base
-                let x = x
-                    .into_vec()
-                    .into_iter()
-                    .map(|el| match el.upcast_to_string() {
-                        Str(x) => x,
-                        _ => unimplemented!(),
-                    })
-                    .join("");
-                self.push(Num(i64::from_str_radix(&x, radix as u32)?));
+                let mut digits = String::new();
+                for el in x.into_vec() {
+                    match el.upcast_to_string() {
+                        Some(Str(x)) => digits.push_str(&x),
+                        _ => return Err(self.type_error("base", &types)),
+                    }
+                }
+                let radix = match radix.to_u32() {
+                    Some(r) if (2..=36).contains(&r) => r,
+                    _ => return Err(ErrorKind::InvalidBase(radix).into()),
+                };
+                let num = match BigInt::parse_bytes(digits.as_bytes(), radix) {
+                    Some(num) => num,
+                    None => return Err(ErrorKind::InvalidNumber(digits).into()),
+                };
+                self.push(Num(num));
             }
             (Num(radix), Num(x)) => {
-                // converto x to `radix` base
+                // converto x to `radix` base, with at most a digit per bit
+                self.allocate(x.bits() + 1, ITEM_SIZE)?;
                 self.push(Array(
-                    to_string_num(x, radix as u32)?
+                    to_string_num(x, &radix)?
                         .chars()
                         .map(|c| Str(c.to_string()))
                         .collect_vec()
-                        .into_boxed_slice(),
+                        .into(),
                 ))
             }
-            _ => unimplemented!(),
+            _ => return Err(self.type_error("base", &types)),
         }
         Ok(())
     }
 
     pub fn assign(&mut self, name: String) -> GSErr {
-        let item = self.peek()?;
+        let item = self.peek()?.clone();
         self.add_variable(name, item);
         Ok(())
     }
     pub fn exec_variable(&mut self, name: &str) -> GSErr {
-        match self.get_variable(name) {
-            Ok(value) => {
-                if let Block(ref items) = value {
-                    self.exec_items(items)?;
-                } else {
-                    self.push(value)
-                }
-                Ok(())
+        match self.variables.get(name) {
+            Some(Block(code)) => {
+                let ops = code.ops();
+                self.exec_ops(&ops)
             }
-            Err(err) => Err(err),
+            _ => self.push_variable(name),
         }
     }
 }
 
 /// convert number in custom base number string
-fn to_string_num(mut num: i64, base: u32) -> Result<String, String> {
-    if base < 2 || base > 33 {
-        return Err(String::from("invalid base"));
-    }
+fn to_string_num(mut num: BigInt, base: &BigInt) -> Result<String, GSError> {
+    let base = match base.to_u8() {
+        Some(base) if (2..=33).contains(&base) => base,
+        _ => return Err(ErrorKind::InvalidBase(base.clone()).into()),
+    };
 
     let mut str_num = String::new();
-    let base = base as i64;
-    let mut count = 0;
 
-    while num > 0 {
-        let radix_mask = (base).pow(count);
-        let digit = ((num / radix_mask) % base) as u8;
+    while num.is_positive() {
+        let (quot, digit) = num.div_rem(&base.into());
+        let digit = digit.to_u8().unwrap_or(0);
 
         let ch = if digit >= 10 {
             (b'A' + (digit - 10)) as char
@@ -926,9 +1183,7 @@ fn to_string_num(mut num: i64, base: u32) -> Result<String, String> {
         };
 
         str_num.push(ch);
-
-        count += 1;
-        num -= digit as i64 * radix_mask;
+        num = quot;
     }
 
     Ok(str_num.chars().rev().collect())
@@ -0,0 +1,161 @@
+use std::char;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use items::{ErrorKind, GSError, Item};
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+/// A Rust value that can be pushed as an `Item`.
+///
+/// Implemented for every type convertible into an `Item`.
+pub trait IntoItem {
+    fn into_item(self) -> Item;
+}
+
+impl<T: Into<Item>> IntoItem for T {
+    fn into_item(self) -> Item {
+        self.into()
+    }
+}
+
+/// A Rust value that can be read from an `Item`.
+///
+/// Numbers are read as integers, booleans and characters, strings as
+/// `String` and arrays as `Vec`s of any readable type. Booleans accept any
+/// item and follow its truthiness.
+pub trait FromItem: Sized {
+    fn from_item(item: Item) -> Result<Self, GSError>;
+}
+
+fn unexpected(expected: &'static str, item: &Item) -> GSError {
+    ErrorKind::UnexpectedType {
+        expected,
+        found: item.type_name(),
+    }
+    .into()
+}
+
+impl FromItem for Item {
+    fn from_item(item: Item) -> Result<Self, GSError> {
+        Ok(item)
+    }
+}
+
+impl FromItem for BigInt {
+    fn from_item(item: Item) -> Result<Self, GSError> {
+        match item {
+            Item::Num(x) => Ok(x),
+            x => Err(unexpected("number", &x)),
+        }
+    }
+}
+
+impl FromItem for i64 {
+    fn from_item(item: Item) -> Result<Self, GSError> {
+        let x = BigInt::from_item(item)?;
+        x.to_i64()
+            .ok_or_else(|| ErrorKind::NumberOutOfRange(x).into())
+    }
+}
+
+impl FromItem for char {
+    fn from_item(item: Item) -> Result<Self, GSError> {
+        let x = BigInt::from_item(item)?;
+        match x.to_u32().and_then(char::from_u32) {
+            Some(c) => Ok(c),
+            None => Err(ErrorKind::NumberOutOfRange(x).into()),
+        }
+    }
+}
+
+impl FromItem for bool {
+    fn from_item(item: Item) -> Result<Self, GSError> {
+        Ok(item.is_true())
+    }
+}
+
+impl FromItem for String {
+    fn from_item(item: Item) -> Result<Self, GSError> {
+        match item {
+            Item::Str(x) => Ok(x),
+            x => Err(unexpected("string", &x)),
+        }
+    }
+}
+
+impl<T: FromItem> FromItem for Vec<T> {
+    fn from_item(item: Item) -> Result<Self, GSError> {
+        match item {
+            Item::Array(x) => x.iter().map(|el| T::from_item(el.clone())).collect(),
+            x => Err(unexpected("array", &x)),
+        }
+    }
+}
+
+impl From<BigInt> for Item {
+    fn from(x: BigInt) -> Self {
+        Item::Num(x)
+    }
+}
+
+impl From<i64> for Item {
+    fn from(x: i64) -> Self {
+        Item::Num(x.into())
+    }
+}
+
+/// Characters are numbers, as the elements of strings are.
+impl From<char> for Item {
+    fn from(c: char) -> Self {
+        Item::Num((c as u32).into())
+    }
+}
+
+impl From<bool> for Item {
+    fn from(x: bool) -> Self {
+        Item::Num((x as u8).into())
+    }
+}
+
+impl From<String> for Item {
+    fn from(x: String) -> Self {
+        Item::Str(x)
+    }
+}
+
+impl<'a> From<&'a str> for Item {
+    fn from(x: &'a str) -> Self {
+        Item::Str(x.to_string())
+    }
+}
+
+impl<T: Into<Item>> From<Vec<T>> for Item {
+    fn from(x: Vec<T>) -> Self {
+        Item::Array(Rc::new(x.into_iter().map(Into::into).collect()))
+    }
+}
+
+macro_rules! try_from_item {
+    ($($ty:ty),*) => {
+        $(
+            impl TryFrom<Item> for $ty {
+                type Error = GSError;
+
+                fn try_from(item: Item) -> Result<Self, GSError> {
+                    <$ty>::from_item(item)
+                }
+            }
+        )*
+    };
+}
+
+try_from_item!(BigInt, i64, char, bool, String);
+
+impl<T: FromItem> TryFrom<Item> for Vec<T> {
+    type Error = GSError;
+
+    fn try_from(item: Item) -> Result<Self, GSError> {
+        Vec::from_item(item)
+    }
+}
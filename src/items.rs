@@ -1,44 +1,354 @@
 #![macro_use]
 extern crate itertools;
+extern crate num_bigint;
+extern crate num_traits;
 
 use itertools::Itertools;
+use limits::Limit;
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+use parser::{parse, Op};
+use std::cell::OnceCell;
 use std::cmp::Ordering;
-use std::num::ParseIntError;
-use std::{char, fmt};
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, Range};
+use std::rc::Rc;
+use std::{char, error, fmt, io};
+use vm::Program;
 
-#[derive(Debug, PartialEq)]
-pub enum GSError {
-    Parse(String),
-    Runtime(String),
+/// Location of a token in the source, `line` and `column` start from 1.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Span {
+    /// Byte offset of the first character of the token.
+    pub start: usize,
+    /// Byte offset one past the last character of the token.
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
-impl From<ParseIntError> for GSError {
-    fn from(e: ParseIntError) -> Self {
-        GSError::Runtime(format!("{}", e))
+impl Span {
+    /// Build the span of the bytes `start..end` of `input`.
+    pub fn new(input: &str, start: usize, end: usize) -> Span {
+        let before = &input[..start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Span {
+            start,
+            end,
+            line,
+            column: before[line_start..].chars().count() + 1,
+        }
     }
 }
 
-impl From<String> for GSError {
-    fn from(e: String) -> Self {
-        GSError::Runtime(e)
+/// The different errors that can occur while lexing or executing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// An operator needed more values than the stack holds.
+    StackUnderflow,
+    /// `]` was executed without a matching `[`.
+    MarkerUnderflow,
+    /// An operator was applied to operands of types it does not support.
+    ///
+    /// `types` lists the operand types from the bottom to the top of the
+    /// stack and `depth` is the stack length before the operands were popped.
+    TypeMismatch {
+        op: String,
+        types: Vec<&'static str>,
+        depth: usize,
+    },
+    UndefinedVariable(String),
+    DivisionByZero,
+    NegativePower,
+    NegativeRepeat,
+    /// A step of zero was given to `%`.
+    ZeroStep,
+    InvalidBase(BigInt),
+    /// Digits that cannot be read as a number.
+    InvalidNumber(String),
+    /// Upper bound of `rand` that is zero or too large.
+    InvalidRange(BigInt),
+    /// A number too large to be used as a count, length or index.
+    NumberOutOfRange(BigInt),
+    UnterminatedString,
+    /// A malformed escape sequence in a double-quoted string, starting with
+    /// the given characters.
+    InvalidEscape(String),
+    UnterminatedBlock,
+    UnexpectedChar(char),
+    /// A `:` not followed by a variable name.
+    EmptyAssignment,
+    /// Reading the input or writing the output failed.
+    Io(io::ErrorKind),
+    /// The program went beyond one of the `Limits` of the interpreter.
+    LimitExceeded(Limit),
+    /// An item converted to a Rust type that cannot hold it.
+    UnexpectedType {
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            ErrorKind::MarkerUnderflow => write!(f, "`]` without a matching `[`"),
+            ErrorKind::TypeMismatch { op, types, depth } => write!(
+                f,
+                "invalid operand types for `{}`: {} (stack depth {})",
+                op,
+                types.join(", "),
+                depth
+            ),
+            ErrorKind::UndefinedVariable(name) => write!(f, "undefined variable `{}`", name),
+            ErrorKind::DivisionByZero => write!(f, "division by zero"),
+            ErrorKind::NegativePower => write!(f, "cannot raise to negative power"),
+            ErrorKind::NegativeRepeat => write!(f, "cannot repeat a negative number of times"),
+            ErrorKind::ZeroStep => write!(f, "step must not be zero"),
+            ErrorKind::InvalidBase(base) => write!(f, "invalid base: {}", base),
+            ErrorKind::InvalidNumber(digits) => write!(f, "invalid number: {}", digits),
+            ErrorKind::InvalidRange(x) => write!(f, "invalid random range: {}", x),
+            ErrorKind::NumberOutOfRange(x) => write!(f, "number out of range: {}", x),
+            ErrorKind::UnterminatedString => write!(f, "unterminated string literal"),
+            ErrorKind::InvalidEscape(seq) => write!(f, "invalid escape sequence `{}`", seq),
+            ErrorKind::UnterminatedBlock => write!(f, "unterminated block, expected `}}`"),
+            ErrorKind::UnexpectedChar(ch) => write!(f, "unexpected character `{}`", ch),
+            ErrorKind::EmptyAssignment => write!(f, "missing variable name after `:`"),
+            ErrorKind::Io(kind) => write!(f, "I/O error: {}", kind),
+            ErrorKind::LimitExceeded(limit) => write!(f, "{} limit exceeded", limit),
+            ErrorKind::UnexpectedType { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+        }
     }
 }
 
+/// Error raised while lexing or executing a program.
+///
+/// `span` points to the offending token of the source given to
+/// `Interpreter::exec`. Errors raised inside a block point to the token that
+/// executed the block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GSError {
+    pub kind: ErrorKind,
+    pub span: Option<Span>,
+}
+
+impl GSError {
+    pub fn new(kind: ErrorKind, span: Span) -> Self {
+        GSError {
+            kind,
+            span: Some(span),
+        }
+    }
+
+    /// Attach `span` to the error, replacing any previous one.
+    pub fn with_span(self, span: Span) -> Self {
+        GSError::new(self.kind, span)
+    }
+}
+
+impl From<ErrorKind> for GSError {
+    fn from(kind: ErrorKind) -> Self {
+        GSError { kind, span: None }
+    }
+}
+
+impl From<io::Error> for GSError {
+    fn from(err: io::Error) -> Self {
+        ErrorKind::Io(err.kind()).into()
+    }
+}
+
+impl fmt::Display for GSError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} at {}:{}", self.kind, span.line, span.column),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl error::Error for GSError {}
+
 /// An `Item` can exist on the stack.
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Item {
     Var(String),
     Assign(String),
-    Num(i64),
+    Num(BigInt),
     Str(String),
-    Array(Box<[Item]>),
-    Block(Box<[Item]>),
+    Array(Rc<Vec<Item>>),
+    Block(Code),
+}
+
+/// The body of a block, which keeps the source it was written as.
+///
+/// Blocks built by operators have no source and are shown as their items
+/// separated by spaces. Blocks compare by their items alone.
+///
+/// The body is parsed and compiled when first run, and copies of the block
+/// share the result.
+#[derive(Clone)]
+pub struct Code(Rc<CodeData>);
+
+struct CodeData {
+    items: Vec<Item>,
+    source: Option<Source>,
+    ops: OnceCell<Rc<[Op]>>,
+    program: OnceCell<Rc<Program>>,
+}
+
+// The text of a block literal and the range of each of its items in it.
+struct Source {
+    text: String,
+    tokens: Vec<Range<usize>>,
+}
+
+impl CodeData {
+    fn new(items: Vec<Item>, source: Option<Source>) -> CodeData {
+        CodeData {
+            items,
+            source,
+            ops: OnceCell::new(),
+            program: OnceCell::new(),
+        }
+    }
+}
+
+impl Code {
+    /// The body of a block literal, `text` being the source between its
+    /// braces and `tokens` the range of each item in it.
+    pub(crate) fn with_source(items: Vec<Item>, text: String, tokens: Vec<Range<usize>>) -> Code {
+        let source = Source { text, tokens };
+        Code(Rc::new(CodeData::new(items, Some(source))))
+    }
+
+    /// The text between the braces of the block literal, if it is one.
+    pub fn source(&self) -> Option<&str> {
+        self.0.source.as_ref().map(|source| source.text.as_str())
+    }
+
+    /// The source text of the `i`th item, if the block is a literal.
+    pub(crate) fn token(&self, i: usize) -> Option<&str> {
+        let source = self.0.source.as_ref()?;
+        Some(&source.text[source.tokens[i].clone()])
+    }
+
+    /// The block running `self` then `other`.
+    ///
+    /// The sources of literals are joined with a space, as the reference
+    /// does.
+    pub(crate) fn concat(self, other: Code) -> Code {
+        let source = match (&self.0.source, &other.0.source) {
+            (Some(x), Some(y)) => {
+                let offset = x.text.len() + 1;
+                let shifted = y.tokens.iter().map(|r| r.start + offset..r.end + offset);
+                Some(Source {
+                    text: format!("{} {}", x.text, y.text),
+                    tokens: x.tokens.iter().cloned().chain(shifted).collect(),
+                })
+            }
+            _ => None,
+        };
+        let mut items = self.into_vec();
+        items.extend(other.into_vec());
+        Code(Rc::new(CodeData::new(items, source)))
+    }
+
+    /// The body parsed for the tree engine.
+    pub(crate) fn ops(&self) -> Rc<[Op]> {
+        Rc::clone(self.0.ops.get_or_init(|| parse(self).into()))
+    }
+
+    /// The body compiled for the bytecode engine.
+    pub(crate) fn program(&self) -> Rc<Program> {
+        Rc::clone(
+            self.0
+                .program
+                .get_or_init(|| Rc::new(Program::compile(self))),
+        )
+    }
+
+    // Write the body as it is shown between the braces.
+    fn write_body(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.source() {
+            Some(source) => f.write_str(source),
+            None => write!(f, "{}", self.iter().join(" ")),
+        }
+    }
+}
+
+impl From<Vec<Item>> for Code {
+    fn from(items: Vec<Item>) -> Self {
+        Code(Rc::new(CodeData::new(items, None)))
+    }
+}
+
+impl Deref for Code {
+    type Target = [Item];
+
+    fn deref(&self) -> &[Item] {
+        &self.0.items
+    }
+}
+
+impl PartialEq for Code {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Code {}
+
+impl Hash for Code {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
 }
 
-macro_rules! Var {
-    ($x:expr) => {{
-        Item::Var($x.to_string())
-    }};
+impl PartialOrd for Code {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Code {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl fmt::Debug for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Take the elements out of the shared contents of an array or block.
+///
+/// The elements are moved when the contents are not shared and cloned
+/// otherwise, so compound items are only copied when written to.
+pub trait IntoVec {
+    fn into_vec(self) -> Vec<Item>;
+}
+
+impl IntoVec for Rc<Vec<Item>> {
+    fn into_vec(self) -> Vec<Item> {
+        Rc::try_unwrap(self).unwrap_or_else(|items| (*items).clone())
+    }
+}
+
+impl IntoVec for Code {
+    fn into_vec(self) -> Vec<Item> {
+        match Rc::try_unwrap(self.0) {
+            Ok(data) => data.items,
+            Err(data) => data.items.clone(),
+        }
+    }
 }
 
 /// Allow `to_string` conversion for `Item`'s
@@ -47,7 +357,7 @@ impl fmt::Display for Item {
         match self {
             Item::Var(x) => write!(f, "{}", x),
             Item::Num(ref x) => write!(f, "{}", x),
-            Item::Str(ref x) => write!(f, "\"{}\"", x.replace("\n", "\\n")),
+            Item::Str(ref x) => write_inspect(f, x),
             Item::Array(ref x) => {
                 write!(f, "[")?;
                 write!(f, "{}", x.iter().join(" "))?;
@@ -55,7 +365,7 @@ impl fmt::Display for Item {
             }
             Item::Block(ref x) => {
                 write!(f, "{{")?;
-                write!(f, "{}", x.iter().join(" "))?;
+                x.write_body(f)?;
                 write!(f, "}}")
             }
             Item::Assign(x) => write!(f, ":{}", x),
@@ -63,6 +373,38 @@ impl fmt::Display for Item {
     }
 }
 
+// Write `string` as a double-quoted literal that lexes back to it, escaping
+// characters as Ruby's `String#inspect` does.
+fn write_inspect(f: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    f.write_char('"')?;
+    let mut chars = string.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' | '\\' => write!(f, "\\{}", ch)?,
+            '#' if matches!(chars.peek(), Some('{') | Some('$') | Some('@')) => {
+                f.write_str("\\#")?
+            }
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\r' => f.write_str("\\r")?,
+            '\x1b' => f.write_str("\\e")?,
+            '\x07' => f.write_str("\\a")?,
+            '\x08' => f.write_str("\\b")?,
+            '\x0c' => f.write_str("\\f")?,
+            '\x0b' => f.write_str("\\v")?,
+            ch if ch.is_control() => write!(f, "\\x{:02X}", ch as u32)?,
+            ch => f.write_char(ch)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl PartialOrd for Item {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Ord for Item {
     fn cmp(&self, other: &Self) -> Ordering {
         match (self, other) {
@@ -71,6 +413,8 @@ impl Ord for Item {
 
             (Item::Num(a), Item::Num(b)) => a.cmp(b),
             (Item::Str(a), Item::Str(b)) => a.cmp(b),
+            (Item::Array(a), Item::Array(b)) => a.cmp(b),
+            (Item::Block(a), Item::Block(b)) => a.cmp(b),
 
             _ => Ordering::Equal,
         }
@@ -78,26 +422,49 @@ impl Ord for Item {
 }
 
 impl Item {
+    /// Name of the type of the `Item`, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Item::Var(_) => "variable",
+            Item::Assign(_) => "assignment",
+            Item::Num(_) => "number",
+            Item::Str(_) => "string",
+            Item::Array(_) => "array",
+            Item::Block(_) => "block",
+        }
+    }
+
     /// Upcast the specified `Item` into an `Item::Array`
     ///
-    /// Accepts: Num, Array
+    /// Accepts: Num, Array, String
+    /// Returns `None` for any other `Item`.
     ///
     /// ### Num
     /// Transforms into a single element array with the number.
     ///
     /// ### Array
     /// Nop
-    pub fn upcast_to_array(self) -> Item {
+    ///
+    /// ### Str
+    /// Transforms into the array of the character codes.
+    pub fn upcast_to_array(self) -> Option<Item> {
         match self {
-            x @ Item::Num(_) => Item::Array(vec![x].into_boxed_slice()),
-            x @ Item::Array(_) => x,
-            _ => panic!("upcast_to_array only accepts num, array"),
+            x @ Item::Num(_) => Some(Item::Array(vec![x].into())),
+            x @ Item::Array(_) => Some(x),
+            Item::Str(x) => Some(Item::Array(
+                x.chars()
+                    .map(|c| Item::Num((c as u32).into()))
+                    .collect_vec()
+                    .into(),
+            )),
+            _ => None,
         }
     }
 
     /// Upcast the specified `Item` into a `Item::Str`
     ///
-    /// Accepts: Num, Array, String
+    /// Accepts: Num, Array, String, Block
+    /// Returns `None` for any other `Item`.
     ///
     /// ### Num
     /// Parses the integer as a string. `34 => '34'`.
@@ -108,61 +475,98 @@ impl Item {
     ///
     /// ### Str
     /// Nop
-    pub fn upcast_to_string(self) -> Item {
+    ///
+    /// ### Block
+    /// The source of the block without the surrounding braces.
+    pub fn upcast_to_string(self) -> Option<Item> {
         match self {
-            Item::Num(val) => Item::Str(val.to_string()),
-            Item::Array(items) => Item::Str(
-                items
-                    .into_vec()
-                    .into_iter()
-                    .map(|item| {
-                        if let Item::Num(val) = item {
-                            char::from_u32(val as u32).unwrap().to_string()
-                        } else if let Item::Str(val) = item.upcast_to_string() {
-                            val
-                        } else {
-                            panic!("upcast_to_string only accepts Num, Array, String")
-                        }
-                    })
-                    .join(""),
-            ),
-            x @ Item::Str(_) => x,
-            _ => panic!("upcast_to_string only accepts Num, Array, String"),
+            Item::Num(val) => Some(Item::Str(val.to_string())),
+            Item::Array(items) => {
+                let mut res = String::new();
+                for item in items.into_vec() {
+                    match item {
+                        Item::Num(val) => res.push(val.to_u32().and_then(char::from_u32)?),
+                        item => match item.upcast_to_string()? {
+                            Item::Str(val) => res.push_str(&val),
+                            _ => return None,
+                        },
+                    }
+                }
+                Some(Item::Str(res))
+            }
+            x @ Item::Str(_) => Some(x),
+            Item::Block(code) => Some(Item::Str(match code.source() {
+                Some(source) => source.to_string(),
+                None => code.iter().join(" "),
+            })),
+            _ => None,
         }
     }
 
     /// Upcast the specified `Item` into a `Item::Block`
     ///
     /// Accepts: Num, Array, String, Block
+    /// Returns `None` for any other `Item`.
     ///
     /// ### Num
-    pub fn upcast_to_block(self) -> Item {
+    pub fn upcast_to_block(self) -> Option<Item> {
         match self {
-            x @ Item::Num(_) => Item::Block(vec![x].into_boxed_slice()),
+            x @ Item::Num(_) => Some(Item::Block(vec![x].into())),
             Item::Array(items) => {
                 let mut res: Vec<Item> = Vec::new();
                 for item in items.into_vec() {
-                    if let Item::Block(val) = item.upcast_to_block() {
-                        for i in val.into_vec() {
-                            res.push(i);
-                        }
-                    } else {
-                        panic!("upcast_to_block only accepts Num, Array, String, Block")
+                    match item.upcast_to_block()? {
+                        Item::Block(val) => res.extend(val.into_vec()),
+                        _ => return None,
                     }
                 }
-                Item::Block(res.into_boxed_slice())
+                Some(Item::Block(res.into()))
             }
-            x @ Item::Str(_) => Item::Block(vec![x].into_boxed_slice()),
-            x @ Item::Block(_) => x,
-            _ => panic!("upcast_to_block only accepts Num, Array, String, Block"),
+            x @ Item::Str(_) => Some(Item::Block(vec![x].into())),
+            x @ Item::Block(_) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Render the `Item` as GolfScript prints it with `print` or at the end
+    /// of a program.
+    ///
+    /// Strings are written raw, arrays are the concatenation of the output
+    /// of their elements and numbers and blocks are written as their source.
+    /// The inspect form used by `` ` `` is given by `Display`.
+    pub fn output(&self) -> String {
+        let mut out = String::new();
+        self.write_output(&mut out);
+        out
+    }
+
+    fn write_output(&self, out: &mut String) {
+        match self {
+            Item::Str(x) => out.push_str(x),
+            Item::Array(items) => items.iter().for_each(|x| x.write_output(out)),
+            x => out.push_str(&x.to_string()),
+        }
+    }
+
+    /// Size of the `Item` as bounded by `Limits::max_item_size`: the number
+    /// of elements of an array or block and the number of bytes of a string
+    /// or number.
+    pub fn size(&self) -> usize {
+        match self {
+            Item::Num(x) => x.bits().div_ceil(8),
+            Item::Str(x) => x.len(),
+            Item::Array(x) => x.len(),
+            Item::Block(x) => x.len(),
+            Item::Var(_) | Item::Assign(_) => 0,
         }
     }
 
     pub fn is_true(&self) -> bool {
         match self {
-            Item::Num(x) if *x != 0 => true,
+            Item::Num(x) if !x.is_zero() => true,
             Item::Str(x) if x.as_str() != "" => true,
-            Item::Array(x) | Item::Block(x) if !x.is_empty() => true,
+            Item::Array(x) if !x.is_empty() => true,
+            Item::Block(x) if !x.is_empty() => true,
             _ => false,
         }
     }
@@ -1,180 +1,287 @@
-use std::iter;
-use std::str;
+use std::char;
+use std::iter::Peekable;
+use std::str::Chars;
 
-use items::{GSError, Item};
+use items::{Code, ErrorKind, GSError, Item, Span};
+use num_bigint::BigInt;
 
-type CharStream<'a> = iter::Peekable<str::Chars<'a>>;
-
-pub fn lex(input: &str) -> Result<Box<[Item]>, GSError> {
-    let mut chars = input.chars().peekable();
+/// Split `input` into tokens as the reference interpreter does with
+///
+/// ```text
+/// /[a-zA-Z_][a-zA-Z0-9_]*|'(?:\\.|[^'])*'?|"(?:\\.|[^"])*"?|-?[0-9]+|#[^\n\r]*|./m
+/// ```
+///
+/// Whitespaces and comments are tokens too, so joining the tokens gives back
+/// `input`.
+pub fn tokenize(input: &str) -> Vec<&str> {
     let mut tokens = Vec::new();
-
-    while let Some(ch) = lex_item(&mut chars) {
-        tokens.push(ch?);
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (token, tail) = rest.split_at(token_len(rest));
+        tokens.push(token);
+        rest = tail;
     }
-
-    Ok(tokens.into_boxed_slice())
+    tokens
 }
 
-fn lex_variable(chars: &mut CharStream) -> String {
-    // Match either a single symbol or a variable name
-    match chars.peek() {
-        Some('+') | Some('-') | Some('!') | Some('@') | Some('$') | Some('*') | Some('/')
-        | Some('%') | Some('|') | Some('&') | Some('^') | Some('\\') | Some(';') | Some('<')
-        | Some('>') | Some('=') | Some('.') | Some('?') | Some('(') | Some(')') | Some('[')
-        | Some(']') | Some('~') | Some('`') | Some(',') => {
-            return chars.next().unwrap().to_string();
+// Length in bytes of the token starting `input`, which is not empty.
+fn token_len(input: &str) -> usize {
+    let bytes = input.as_bytes();
+    let count =
+        |from: usize, pred: fn(&u8) -> bool| bytes[from..].iter().take_while(|&b| pred(b)).count();
+
+    match bytes[0] {
+        b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+            1 + count(1, |b| b.is_ascii_alphanumeric() || *b == b'_')
         }
-        Some(_) | None => (),
-    }
-    let mut string = String::new();
-    loop {
-        match chars.peek() {
-            Some(ch) if ch.is_alphanumeric() || *ch == '_' => {
-                string.push(*ch);
-                chars.next();
+        quote @ b'\'' | quote @ b'"' => {
+            let mut chars = input.char_indices().skip(1);
+            while let Some((i, ch)) = chars.next() {
+                if ch == '\\' {
+                    chars.next();
+                } else if ch == quote as char {
+                    return i + 1;
+                }
             }
-            Some(_) | None => break,
+            // the closing quote is optional
+            input.len()
         }
+        b'0'..=b'9' => count(0, u8::is_ascii_digit),
+        b'-' if count(1, u8::is_ascii_digit) > 0 => 1 + count(1, u8::is_ascii_digit),
+        b'#' => input.find(['\n', '\r']).unwrap_or(input.len()),
+        _ => input.chars().next().map_or(1, char::len_utf8),
     }
-    string
 }
 
-fn lex_item(mut chars: &mut CharStream) -> Option<Result<Item, GSError>> {
-    loop {
-        let item = match chars.peek() {
-            Some('#') => {
-                chars.next();
-                while let Some(&ch) = chars.peek() {
-                    chars.next();
-                    if ch == '\n' {
-                        break;
-                    }
-                }
-                continue;
-            }
+/// Whitespaces and comments, which do nothing unless assigned to.
+pub(crate) fn is_blank(token: &str) -> bool {
+    token.starts_with('#') || token.chars().all(char::is_whitespace)
+}
 
-            Some('"') => {
-                chars.next();
-                let mut string = String::new();
-                loop {
-                    match chars.next() {
-                        Some('\\') => match chars.next() {
-                            Some(ch) => string.push(ch),
-                            None => {
-                                return Some(Err(GSError::Parse(
-                                    "invalid escape sequence".to_string(),
-                                )));
-                            }
-                        },
-
-                        Some('"') => break,
-                        Some(ch) => string.push(ch),
-                        None => {
-                            return Some(Err(GSError::Parse(
-                                "eof while scanning string literal".to_string(),
-                            )));
-                        }
-                    }
-                }
-                let string = string.replace("\\\\", "\\").replace("\\\"", "\"");
-                Item::Str(string)
-            }
+/// Lex the input, pairing each top-level item with its position.
+pub fn lex_spanned(input: &str) -> Result<Vec<(Item, Span)>, GSError> {
+    let mut offset = 0;
+    let mut tokens = tokenize(input).into_iter().map(|token| {
+        offset += token.len();
+        (offset - token.len(), token)
+    });
 
-            Some('{') => {
-                chars.next();
-                let mut block_items = Vec::new();
-                loop {
-                    match chars.peek() {
-                        Some(&'}') => {
-                            chars.next();
-                            break;
-                        }
-
-                        // We must handle whitespace here else we can read
-                        // `None` and skip final '}'
-                        Some(&ch) if ch.is_whitespace() => {
-                            chars.next();
-                            continue;
-                        }
-
-                        // Handle eof/`None` on `lex_item` call
-                        Some(_) | None => {
-                            let item = match lex_item(&mut chars) {
-                                Some(ch) => ch,
-                                None => {
-                                    return Some(Err(GSError::Parse(
-                                        "eof while scanning for '}'".to_string(),
-                                    )))
-                                }
-                            };
-
-                            block_items.push(if let Ok(val) = item {
-                                val
-                            } else {
-                                return Some(item);
-                            });
-                        }
+    let mut items = Vec::new();
+    while let Some(token) = tokens.next() {
+        let start = token.0;
+        let (item, end) = lex_item(input, token, &mut tokens)?;
+        items.push((item, Span::new(input, start, end)));
+    }
+    Ok(items)
+}
+
+// Build the item starting with `token`, taking the tokens it spans from
+// `tokens`, and return it with the offset of its end.
+fn lex_item<'a, I>(
+    input: &str,
+    token: (usize, &'a str),
+    tokens: &mut I,
+) -> Result<(Item, usize), GSError>
+where
+    I: Iterator<Item = (usize, &'a str)>,
+{
+    let (start, text) = token;
+    let end = start + text.len();
+    // Error spanning from the start of the item to `end`
+    let error = |kind, end| Err(GSError::new(kind, Span::new(input, start, end)));
+
+    let item = match text {
+        "{" => {
+            let mut block_items = Vec::new();
+            let mut ranges = Vec::new();
+            loop {
+                match tokens.next() {
+                    Some((i, "}")) => {
+                        let text = input[end..i].to_string();
+                        let code = Code::with_source(block_items, text, ranges);
+                        return Ok((Item::Block(code), i + 1));
+                    }
+                    Some(token) => {
+                        let (item, item_end) = lex_item(input, token, tokens)?;
+                        block_items.push(item);
+                        ranges.push(token.0 - end..item_end - end);
                     }
+                    None => return error(ErrorKind::UnterminatedBlock, input.len()),
                 }
-                Item::Block(block_items.into_boxed_slice())
             }
+        }
 
-            Some(&ch) if ch.is_digit(10) => {
-                chars.next();
-                let mut num = String::new();
-                num.push(ch);
-                while let Some(&ch) = chars.peek() {
-                    if ch.is_digit(10) {
-                        num.push(ch);
-                        chars.next();
-                    } else {
-                        break;
-                    }
+        "}" => return error(ErrorKind::UnexpectedChar('}'), end),
+
+        // any token can be assigned to, whitespaces and comments included
+        ":" => match tokens.next() {
+            Some((i, name)) => {
+                let end = i + name.len();
+                // literals are named after their token, so `:"a"` leaves
+                // `'a'` and `:7` leaves `007` alone
+                if let Some(Err(kind)) = literal(name) {
+                    return error(kind, end);
                 }
-                Item::Num(num.parse::<i64>().unwrap())
+                return Ok((Item::Assign(name.to_string()), end));
             }
+            None => return error(ErrorKind::EmptyAssignment, end),
+        },
 
-            // If we encounter a '-' immediately followed by a number, this
-            // is bound to the number instead of treated as an operator.
-            Some(ch) if *ch == '-' => {
-                chars.next();
-                match chars.peek() {
-                    Some(&ch) if ch.is_digit(10) => match lex_item(&mut chars) {
-                        Some(Ok(Item::Num(x))) => Item::Num(-x),
-                        Some(Err(e)) => return Some(Err(e)),
-                        _ => unreachable!(),
-                    },
-
-                    _ => Var!("-"),
+        _ => match literal(text) {
+            Some(Ok(item)) => item,
+            Some(Err(kind)) => return error(kind, end),
+            None => Item::Var(text.to_string()),
+        },
+    };
+
+    Ok((item, end))
+}
+
+// The string or number of a literal token, `None` for other tokens.
+fn literal(token: &str) -> Option<Result<Item, ErrorKind>> {
+    if is_string(token) {
+        Some(unquote(token).map(Item::Str))
+    } else if is_number(token) {
+        Some(match token.parse::<BigInt>() {
+            Ok(num) => Ok(Item::Num(num)),
+            Err(_) => Err(ErrorKind::InvalidNumber(token.to_string())),
+        })
+    } else {
+        None
+    }
+}
+
+pub(crate) fn is_string(token: &str) -> bool {
+    token.starts_with(['"', '\''])
+}
+
+pub(crate) fn is_number(token: &str) -> bool {
+    let digits = token.strip_prefix('-').unwrap_or(token);
+    digits.starts_with(|ch: char| ch.is_ascii_digit())
+}
+
+// The contents of a quoted string token.
+//
+// Single-quoted strings only escape `'` and `\`, double-quoted ones support
+// the escapes of Ruby but not its interpolation.
+fn unquote(token: &str) -> Result<String, ErrorKind> {
+    let mut chars = token.chars().peekable();
+    let quote = chars.next();
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            Some(ch) if Some(ch) == quote => return Ok(string),
+            Some('\\') if quote == Some('"') => escape(&mut chars, &mut string)?,
+            Some('\\') => match chars.next() {
+                Some(ch) if ch == '\'' || ch == '\\' => string.push(ch),
+                Some(ch) => {
+                    string.push('\\');
+                    string.push(ch);
                 }
-            }
+                None => return Err(ErrorKind::UnterminatedString),
+            },
+            Some(ch) => string.push(ch),
+            None => return Err(ErrorKind::UnterminatedString),
+        }
+    }
+}
 
-            Some(ch) if ch.is_whitespace() => {
-                chars.next();
-                continue;
+// Push the character of the escape sequence following a `\` to `string`.
+fn escape(chars: &mut Peekable<Chars>, string: &mut String) -> Result<(), ErrorKind> {
+    let ch = match chars.next() {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('r') => '\r',
+        Some('s') => ' ',
+        Some('e') => '\x1b',
+        Some('a') => '\x07',
+        Some('b') => '\x08',
+        Some('f') => '\x0c',
+        Some('v') => '\x0b',
+        // a backslash before a newline joins the lines
+        Some('\n') => return Ok(()),
+        Some(ch @ '0'..='7') => {
+            let code = digits(chars, 8, 2, ch.to_digit(8));
+            code_point(code.unwrap_or(0) & 0xff, "\\0")?
+        }
+        Some('x') => match digits(chars, 16, 2, None) {
+            Some(code) => code_point(code, "\\x")?,
+            None => return Err(invalid_escape("\\x")),
+        },
+        Some('u') => return unicode(chars, string),
+        Some('c') => control(chars.next(), "\\c")?,
+        Some('C') if chars.next_if_eq(&'-').is_some() => control(chars.next(), "\\C-")?,
+        Some('M') if chars.next_if_eq(&'-').is_some() => match chars.next() {
+            Some(ch) if ch.is_ascii() => code_point(ch as u32 | 0x80, "\\M-")?,
+            _ => return Err(invalid_escape("\\M-")),
+        },
+        Some(ch) => ch,
+        None => return Err(ErrorKind::UnterminatedString),
+    };
+    string.push(ch);
+    Ok(())
+}
+
+// Push the characters of `\uXXXX` or of `\u{X ...}`, which lists code
+// points separated by spaces.
+fn unicode(chars: &mut Peekable<Chars>, string: &mut String) -> Result<(), ErrorKind> {
+    if chars.next_if_eq(&'{').is_none() {
+        let mut code = 0;
+        for _ in 0..4 {
+            match chars.next().and_then(|ch| ch.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return Err(invalid_escape("\\u")),
             }
+        }
+        string.push(code_point(code, "\\u")?);
+        return Ok(());
+    }
 
-            Some(':') => {
+    loop {
+        while chars.next_if_eq(&' ').is_some() {}
+        if chars.next_if_eq(&'}').is_some() {
+            return Ok(());
+        }
+        match digits(chars, 16, 6, None) {
+            Some(code) => string.push(code_point(code, "\\u")?),
+            None => return Err(invalid_escape("\\u")),
+        }
+    }
+}
+
+// Add up to `max` digits in `radix` to `code`, `None` if there is no digit
+// at all.
+fn digits(
+    chars: &mut Peekable<Chars>,
+    radix: u32,
+    max: usize,
+    mut code: Option<u32>,
+) -> Option<u32> {
+    for _ in 0..max {
+        match chars.peek().and_then(|ch| ch.to_digit(radix)) {
+            Some(digit) => {
+                code = Some(code.unwrap_or(0) * radix + digit);
                 chars.next();
-                let var = lex_variable(chars);
-                if var.is_empty() {
-                    return Some(Err(GSError::Parse(
-                        "empty variable name after :".to_string(),
-                    )));
-                }
-                Item::Assign(var)
             }
+            None => break,
+        }
+    }
+    code
+}
 
-            Some(_) => {
-                let var = lex_variable(chars);
-                // variable could be empty?
-                Item::Var(var)
-            }
+// The control character of `\cx` and `\C-x`, `\c?` being delete.
+fn control(ch: Option<char>, seq: &str) -> Result<char, ErrorKind> {
+    match ch {
+        Some('?') => Ok('\x7f'),
+        Some(ch) if ch.is_ascii() => code_point(ch as u32 & 0x9f, seq),
+        _ => Err(invalid_escape(seq)),
+    }
+}
 
-            _ => return None,
-        };
+fn code_point(code: u32, seq: &str) -> Result<char, ErrorKind> {
+    char::from_u32(code).ok_or_else(|| invalid_escape(seq))
+}
 
-        return Some(Ok(item));
-    }
+fn invalid_escape(seq: &str) -> ErrorKind {
+    ErrorKind::InvalidEscape(seq.to_string())
 }
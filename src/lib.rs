@@ -1,193 +1,609 @@
 extern crate itertools;
+extern crate num_bigint;
+extern crate num_integer;
+extern crate num_traits;
 extern crate rand;
 
-use std::collections::HashMap;
-use std::str;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::time::Instant;
+use std::{fmt, mem, str};
 
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+mod builder;
 mod bultins;
+mod convert;
 mod items;
 mod lexer;
+mod limits;
+mod parser;
+mod stack;
+mod vm;
 
+pub use builder::{InterpreterBuilder, PRELUDE};
+pub use convert::{FromItem, IntoItem};
 pub use items::*;
-use lexer::lex;
+pub use lexer::tokenize;
+use lexer::{is_blank, is_number, is_string, lex_spanned};
+pub use limits::{Limit, Limits};
+pub use num_bigint::BigInt;
+use parser::{parse_item, Builtin, Op};
+pub use rand::RngCore;
+pub use stack::Stack;
+use vm::Program;
 use Item::*;
 
-#[derive(Debug, Default)]
+/// How the interpreter executes programs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Engine {
+    /// Walk the parsed items directly.
+    Tree,
+    /// Compile to bytecode first, which is faster on loop-heavy programs.
+    Bytecode,
+}
+
+/// A function implementing a builtin in Rust.
+pub type NativeBuiltin = dyn Fn(&mut Stack) -> Result<(), GSError>;
+
 pub struct Interpreter {
-    stack: Vec<Item>,
+    stack: Stack,
+
+    variables: HashMap<String, Item>,
+
+    /// Builtins whose name was assigned to by the user
+    shadowed: HashSet<Builtin>,
+
+    /// Tokens of number and string literals, whitespaces and comments
+    /// assigned to by the user
+    literals: HashSet<String>,
+
+    /// Builtins registered by the host
+    natives: HashMap<String, Rc<NativeBuiltin>>,
+
+    engine: Engine,
 
-    /// Store all past stack markers
-    marker_stack: Vec<usize>,
+    limits: Limits,
 
+    /// Roll back the stack and variables when a call fails
+    transactional: bool,
+
+    /// Operations executed since the start of the current call
+    ops: u64,
+
+    /// Nesting of the blocks being executed
+    depth: usize,
+
+    /// Approximate bytes allocated by builtins since the start of the call
+    allocated: usize,
+
+    /// End of the time given to the current call
+    deadline: Option<Instant>,
+
+    /// Source of the string pushed by `run`
+    input: Box<dyn Read>,
+
+    /// Destination of `print`, `puts`, `p` and the output of `run`
+    output: Box<dyn Write>,
+
+    /// Source of the numbers returned by `rand`
+    rng: Box<dyn RngCore>,
+
+    /// State left by the prelude, restored by `reset`
+    prelude: Snapshot,
+}
+
+/// The state changed by executing a program.
+///
+/// Items share their arrays and blocks, so taking it copies little more
+/// than the stack and the variable table.
+#[derive(Clone, Default)]
+struct Snapshot {
+    stack: Stack,
     variables: HashMap<String, Item>,
+    shadowed: HashSet<Builtin>,
+    literals: HashSet<String>,
+}
+
+impl Snapshot {
+    fn take(it: &Interpreter) -> Snapshot {
+        Snapshot {
+            stack: it.stack.clone(),
+            variables: it.variables.clone(),
+            shadowed: it.shadowed.clone(),
+            literals: it.literals.clone(),
+        }
+    }
+
+    fn restore(self, it: &mut Interpreter) {
+        it.stack = self.stack;
+        it.variables = self.variables;
+        it.shadowed = self.shadowed;
+        it.literals = self.literals;
+    }
+}
+
+impl fmt::Debug for Interpreter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Interpreter")
+            .field("stack", &self.stack)
+            .field("variables", &self.variables)
+            .field("engine", &self.engine)
+            .field("limits", &self.limits)
+            .field("transactional", &self.transactional)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
 }
 
 impl Interpreter {
+    /// Create an interpreter reading from stdin and writing to stdout.
     pub fn new() -> Self {
-        Interpreter {
-            stack: Vec::new(),
-            marker_stack: Vec::new(),
-            variables: Interpreter::get_default_var(),
-        }
-    }
-
-    fn get_default_var() -> HashMap<String, Item> {
-        let mut variables = HashMap::new();
-        // Set predefined variables
-        variables.insert(
-            "and".to_string(),
-            Block(Box::new([Num(1), Var!("$"), Var!("if")])),
-        );
-        variables.insert(
-            "or".to_string(),
-            Block(Box::new([Num(1), Var!("$"), Var!("\\"), Var!("if")])),
-        );
-        variables.insert(
-            "xor".to_string(),
-            Block(Box::new([
-                Var!("\\"),
-                Var!("!"),
-                Var!("!"),
-                Block(Box::new([Var!("!")])),
-                Var!("*"),
-            ])),
-        );
-        variables.insert("n".to_string(), Str("\n".to_string()));
-        variables.insert(
-            "puts".to_string(),
-            Block(Box::new([
-                Var("print".to_string()),
-                Var("n".to_string()),
-                Var("print".to_string()),
-            ])),
-        );
-        variables.insert("p".to_string(), Block(Box::new([Var!("`"), Var!("puts")])));
-        variables
+        Interpreter::with_io(io::stdin(), io::stdout())
+    }
+
+    /// Create an interpreter reading its input from `input` and writing
+    /// everything it prints to `output`.
+    pub fn with_io<R, W>(input: R, output: W) -> Self
+    where
+        R: Read + 'static,
+        W: Write + 'static,
+    {
+        InterpreterBuilder::new()
+            .io(input, output)
+            .build()
+            .expect("the standard prelude runs")
+    }
+
+    /// Configure an interpreter, such as its prelude, before creating it.
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder::new()
+    }
+
+    /// Create an interpreter reading from stdin and writing to stdout whose
+    /// `rand` returns the same sequence of numbers on each run with `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        let mut it = Interpreter::new();
+        it.set_rng(StdRng::seed_from_u64(seed));
+        it
+    }
+
+    /// Draw the numbers returned by `rand` from `rng`.
+    pub fn set_rng<R: RngCore + 'static>(&mut self, rng: R) {
+        self.rng = Box::new(rng);
+    }
+
+    /// The engine used by `exec` and `exec_items`.
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Select the engine used by `exec` and `exec_items`.
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    /// The limits applied to each call to `exec`, `exec_items` or `run`.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Bound the resources used by each call to `exec`, `exec_items` or
+    /// `run`, programs going beyond them fail with
+    /// `ErrorKind::LimitExceeded`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Whether a failing call to `exec` or `exec_items` leaves the
+    /// interpreter as it was before the call.
+    pub fn transactional(&self) -> bool {
+        self.transactional
+    }
+
+    /// Make failing calls to `exec` and `exec_items` roll back the stack,
+    /// the markers and the variables, which is the default.
+    ///
+    /// Streaming users can turn it off to keep whatever a failing program
+    /// managed to do and avoid copying the state on each call.
+    pub fn set_transactional(&mut self, transactional: bool) {
+        self.transactional = transactional;
+    }
+
+    /// Define a builtin implemented in Rust, called whenever `name` is
+    /// executed.
+    ///
+    /// It replaces any variable or builtin of the same name, and programs
+    /// can still redefine `name` by assigning to it.
+    pub fn register_builtin<F>(&mut self, name: &str, builtin: F)
+    where
+        F: Fn(&mut Stack) -> Result<(), GSError> + 'static,
+    {
+        if let Some(builtin) = Builtin::from_name(name) {
+            self.shadowed.insert(builtin);
+        }
+        self.variables.remove(name);
+        self.natives.insert(name.to_string(), Rc::new(builtin));
     }
 
     /// Execute a string, returning the stack state after execution
+    ///
+    /// Errors point to the token of `input` that raised them.
     pub fn exec(&mut self, input: &str) -> Result<&[Item], GSError> {
-        let items = lex(&input)?;
-        self.exec_items(&items)
+        self.start();
+        self.atomically(|it| it.exec_source(input))?;
+        Ok(&self.stack)
+    }
+
+    /// Run a whole program as the reference interpreter does
+    ///
+    /// The input is read to its end and pushed as a string, then `input` is
+    /// executed and the final stack is written to the output followed by a
+    /// newline.
+    pub fn run(&mut self, input: &str) -> Result<&[Item], GSError> {
+        self.start();
+        let mut stdin = String::new();
+        self.input.read_to_string(&mut stdin)?;
+        self.push(Str(stdin));
+
+        self.exec_source(input)?;
+
+        for item in self.stack.iter() {
+            write!(self.output, "{}", item.output())?;
+        }
+        writeln!(self.output)?;
+        self.output.flush()?;
+        Ok(&self.stack)
     }
 
     /// Execute a sequence of items, returning the stack state after execution
-    // TODO: split this function
     pub fn exec_items(&mut self, items: &[Item]) -> Result<&[Item], GSError> {
-        for item in items {
-            match item {
-                x @ Num(_) | x @ Str(_) | x @ Block(_) => self.push(x.clone()),
-                Assign(name) => self.assign(name.clone())?,
-                Var(name) if self.variables.contains_key(name) => {
-                    self.exec_variable(name.as_str())?
-                }
-                Var(name) if "+" == name.as_str() => self.add()?,
-                Var(name) if "-" == name.as_str() => self.sub()?,
-                Var(name) if "!" == name.as_str() => self.not()?,
-                Var(name) if "@" == name.as_str() => self.at()?,
-                Var(name) if "$" == name.as_str() => self.dollar()?,
-                Var(name) if "*" == name.as_str() => self.mul()?,
-                Var(name) if "/" == name.as_str() => self.div()?,
-                Var(name) if "%" == name.as_str() => self.modulo()?,
-                Var(name) if "|" == name.as_str() => self.or()?,
-                Var(name) if "&" == name.as_str() => self.and()?,
-                Var(name) if "^" == name.as_str() => self.xor()?,
-                Var(name) if "\\" == name.as_str() => self.swap()?,
-                Var(name) if ";" == name.as_str() => self.pop_discard()?,
-                Var(name) if "<" == name.as_str() => self.lt()?,
-                Var(name) if ">" == name.as_str() => self.gt()?,
-                Var(name) if "=" == name.as_str() => self.eq()?,
-                Var(name) if "." == name.as_str() => self.dup()?,
-                Var(name) if "?" == name.as_str() => self.qmark()?,
-                Var(name) if "(" == name.as_str() => self.dec()?,
-                Var(name) if ")" == name.as_str() => self.inc()?,
-                Var(name) if "[" == name.as_str() => self.marker()?,
-                Var(name) if "]" == name.as_str() => self.slice()?,
-                Var(name) if "~" == name.as_str() => self.neg()?,
-                Var(name) if "`" == name.as_str() => self.backtick()?,
-                Var(name) if "," == name.as_str() => self.array()?,
-                Var(name) if "abs" == name.as_str() => self.builtin_abs()?,
-                Var(name) if "if" == name.as_str() => self.builtin_if()?,
-                Var(name) if "rand" == name.as_str() => self.builtin_rand()?,
-                Var(name) if "print" == name.as_str() => self.builtin_print()?,
-                Var(name) if "zip" == name.as_str() => self.builtin_zip()?,
-                Var(name) if "base" == name.as_str() => self.builtin_base()?,
-                Var(name) => {
-                    return Err(GSError::Runtime(format!("variable '{}' not founded", name)))
-                }
-                x => {
-                    return Err(GSError::Runtime(format!(
-                        "invalid token encountered: {:?}",
-                        x
-                    )))
-                }
+        self.start();
+        self.atomically(|it| it.exec_block(&items.to_vec().into()))?;
+        Ok(&self.stack)
+    }
+
+    /// Call the variable or builtin `name` on `args`, returning the values it
+    /// leaves.
+    ///
+    /// The call runs on a stack of its own holding only `args`, so it fails
+    /// with `ErrorKind::StackUnderflow` if it takes more values than it was
+    /// given, and the stack of the interpreter is left as it was.
+    pub fn call(&mut self, name: &str, args: &[Item]) -> Result<Vec<Item>, GSError> {
+        self.start();
+        let outer = mem::replace(&mut self.stack, Stack::new());
+        self.stack.items.extend_from_slice(args);
+        let res = self.atomically(|it| it.exec_block(&vec![Var(name.to_string())].into()));
+        let results = mem::replace(&mut self.stack, outer).items;
+        res.map(|_| results)
+    }
+
+    // Run `f`, restoring the state it started from if it fails and the
+    // interpreter is transactional.
+    fn atomically<F>(&mut self, f: F) -> Result<(), GSError>
+    where
+        F: FnOnce(&mut Interpreter) -> Result<(), GSError>,
+    {
+        if !self.transactional {
+            return f(self);
+        }
+        let snapshot = Snapshot::take(self);
+        let res = f(self);
+        if res.is_err() {
+            snapshot.restore(self);
+        }
+        res
+    }
+
+    // Reset the limit counters at the start of a call.
+    fn start(&mut self) {
+        self.ops = 0;
+        self.depth = 0;
+        self.allocated = 0;
+        self.deadline = self.limits.max_time.map(|time| Instant::now() + time);
+    }
+
+    fn exec_source(&mut self, input: &str) -> Result<(), GSError> {
+        let tokens = lex_spanned(input)?;
+        if self.engine == Engine::Bytecode {
+            return self.run_program(&Program::compile_spanned(input, &tokens));
+        }
+
+        for (item, span) in tokens {
+            let token = &input[span.start..span.end];
+            if let Err(err) = self.exec_op(&parse_item(&item, Some(token))) {
+                return Err(err.with_span(span));
             }
         }
-        // println!("   STACK:{:?} VARIABLE:{:?}", self.stack, self.variables);
+        Ok(())
+    }
 
-        Ok(&self.stack)
+    fn exec_block(&mut self, code: &Code) -> Result<(), GSError> {
+        match self.engine {
+            Engine::Tree => self.exec_ops(&code.ops()),
+            Engine::Bytecode => {
+                self.enter()?;
+                let res = self.run_program(&code.program());
+                self.depth -= 1;
+                res
+            }
+        }
     }
 
-    /// Push a value onto the stack
+    /// Execute a sequence of parsed items.
+    fn exec_ops(&mut self, ops: &[Op]) -> Result<(), GSError> {
+        self.enter()?;
+        let res = ops.iter().try_for_each(|op| self.exec_op(op));
+        self.depth -= 1;
+        res
+    }
+
+    // Account for a nested block.
+    fn enter(&mut self) -> Result<(), GSError> {
+        if self.limits.max_depth.is_some_and(|max| self.depth >= max) {
+            return Err(ErrorKind::LimitExceeded(Limit::Depth).into());
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // Account for an operation about to be executed.
+    fn step(&mut self) -> Result<(), GSError> {
+        self.ops += 1;
+        if self.limits.max_ops.is_some_and(|max| self.ops > max) {
+            return Err(ErrorKind::LimitExceeded(Limit::Ops).into());
+        }
+        // reading the clock is slow, only do it every few operations
+        if let Some(deadline) = self.deadline {
+            if self.ops.is_multiple_of(256) && Instant::now() >= deadline {
+                return Err(ErrorKind::LimitExceeded(Limit::Time).into());
+            }
+        }
+        Ok(())
+    }
+
+    // Check the stack after an operation.
+    fn check_stack(&self) -> Result<(), GSError> {
+        if self
+            .limits
+            .max_stack
+            .is_some_and(|max| self.stack.len() > max)
+        {
+            return Err(ErrorKind::LimitExceeded(Limit::Stack).into());
+        }
+        if let Some(item) = self.stack.last() {
+            self.check_size(item.size())?;
+        }
+        Ok(())
+    }
+
+    /// Check the size of an item before building it.
+    fn check_size(&self, size: usize) -> Result<(), GSError> {
+        if self.limits.max_item_size.is_some_and(|max| size > max) {
+            return Err(ErrorKind::LimitExceeded(Limit::ItemSize).into());
+        }
+        Ok(())
+    }
+
+    /// Account for an item of `len` elements of `unit` bytes before
+    /// building it.
     ///
-    /// # Panics
-    /// panics if the value exceeds the length of a usize.
-    fn push(&mut self, value: Item) {
-        self.stack.push(value)
+    /// Items too large to be allocated at all fail even without limits.
+    fn allocate(&mut self, len: usize, unit: usize) -> Result<(), GSError> {
+        self.check_size(len)?;
+        let bytes = match len.checked_mul(unit) {
+            Some(bytes) if bytes <= isize::MAX as usize => bytes,
+            _ => return Err(ErrorKind::NumberOutOfRange(len.into()).into()),
+        };
+        self.allocated = self.allocated.saturating_add(bytes);
+        if self
+            .limits
+            .max_allocation
+            .is_some_and(|max| self.allocated > max)
+        {
+            return Err(ErrorKind::LimitExceeded(Limit::Allocation).into());
+        }
+        Ok(())
     }
 
-    /// Pop a single value off the stack.
-    fn pop(&mut self) -> Result<Item, GSError> {
-        match self.stack.pop() {
-            Some(value) => {
-                // Resize all markers that are beyond the edge of the stack
-                for marker in self.marker_stack.iter_mut() {
-                    if *marker > self.stack.len() {
-                        *marker = self.stack.len();
-                    }
-                }
-
-                Ok(value)
+    fn exec_op(&mut self, op: &Op) -> Result<(), GSError> {
+        if let Op::Blank(token) = op {
+            if !self.redefined_literal(token) {
+                return Ok(());
             }
+        }
+        self.step()?;
+        match op {
+            Op::Literal(_, token) if self.redefined_literal(token) => self.exec_variable(token)?,
+            Op::Literal(x, _) | Op::Push(x) => self.push(x.clone()),
+            Op::Assign(name) => self.assign(name.clone())?,
+            Op::Builtin(builtin) if self.shadowed.contains(builtin) => {
+                self.exec_variable(builtin.name())?
+            }
+            Op::Builtin(builtin) => self.exec_builtin(*builtin)?,
+            Op::Var(name) | Op::Blank(name) => self.exec_variable(name)?,
+        }
+        self.check_stack()
+    }
 
-            None => Err(GSError::Runtime("stack underflow".to_string())),
+    fn exec_builtin(&mut self, builtin: Builtin) -> Result<(), GSError> {
+        match builtin {
+            Builtin::Add => self.add(),
+            Builtin::Sub => self.sub(),
+            Builtin::Not => self.not(),
+            Builtin::At => self.at(),
+            Builtin::Dollar => self.dollar(),
+            Builtin::Mul => self.mul(),
+            Builtin::Div => self.div(),
+            Builtin::Modulo => self.modulo(),
+            Builtin::Or => self.or(),
+            Builtin::And => self.and(),
+            Builtin::Xor => self.xor(),
+            Builtin::Swap => self.swap(),
+            Builtin::PopDiscard => self.pop_discard(),
+            Builtin::Lt => self.lt(),
+            Builtin::Gt => self.gt(),
+            Builtin::Eq => self.eq(),
+            Builtin::Dup => self.dup(),
+            Builtin::Qmark => self.qmark(),
+            Builtin::Dec => self.dec(),
+            Builtin::Inc => self.inc(),
+            Builtin::Marker => self.marker(),
+            Builtin::Slice => self.slice(),
+            Builtin::Neg => self.neg(),
+            Builtin::Backtick => self.backtick(),
+            Builtin::Array => self.array(),
+            Builtin::Abs => self.builtin_abs(),
+            Builtin::If => self.builtin_if(),
+            Builtin::Do => self.builtin_do(),
+            Builtin::While => self.builtin_while(),
+            Builtin::Until => self.builtin_until(),
+            Builtin::Rand => self.builtin_rand(),
+            Builtin::Print => self.builtin_print(),
+            Builtin::Zip => self.builtin_zip(),
+            Builtin::Base => self.builtin_base(),
         }
     }
 
+    /// Push a value onto the stack
+    pub fn push(&mut self, value: Item) {
+        self.stack.push(value)
+    }
+
+    /// Pop a single value off the stack.
+    pub fn pop(&mut self) -> Result<Item, GSError> {
+        self.stack.pop()
+    }
+
     /// Pop the top two values off the stack.
     fn pop2(&mut self) -> Result<(Item, Item), GSError> {
-        // Order of execution in ',' values is defined in rust.
-        Ok((self.pop()?, self.pop()?))
+        self.stack.pop2()
     }
 
     /// Peek last element of the stack.
-    fn peek(&mut self) -> Result<Item, GSError> {
-        match self.stack.last() {
-            Some(value) => Ok(value.clone()),
-            None => Err(GSError::Runtime("stack underflow".to_string())),
+    pub fn peek(&self) -> Result<&Item, GSError> {
+        self.stack.peek()
+    }
+
+    /// The stack, from the bottom to the top.
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    /// Mutable access to the stack, to pass arguments or take results.
+    pub fn stack_mut(&mut self) -> &mut Stack {
+        &mut self.stack
+    }
+
+    /// Remove every value and marker from the stack.
+    pub fn clear_stack(&mut self) {
+        self.stack.clear();
+    }
+
+    /// The value of a variable, including the predefined ones.
+    pub fn variable(&self, name: &str) -> Option<&Item> {
+        self.variables.get(name)
+    }
+
+    /// Set a variable as `value:name;` would.
+    pub fn set_variable(&mut self, name: &str, value: Item) {
+        self.add_variable(name.to_string(), value);
+    }
+
+    /// Clear the stack and bring the variables back to the ones defined by
+    /// the prelude.
+    ///
+    /// The engine, limits, I/O, random generator and native builtins are
+    /// kept.
+    pub fn reset(&mut self) {
+        self.prelude.clone().restore(self);
+
+        // native builtins replace the definitions of the prelude
+        for name in self.natives.keys() {
+            if let Some(builtin) = Builtin::from_name(name) {
+                self.shadowed.insert(builtin);
+            }
+            self.variables.remove(name);
         }
     }
 
+    // Run the prelude without limits, discarding the values it leaves.
+    fn load_prelude(&mut self, prelude: &str) -> Result<(), GSError> {
+        let limits = mem::take(&mut self.limits);
+        self.start();
+        let res = self.exec_source(prelude);
+        self.limits = limits;
+        self.stack.clear();
+        res
+    }
+
     fn add_variable(&mut self, name: String, value: Item) {
+        if let Some(builtin) = Builtin::from_name(&name) {
+            self.shadowed.insert(builtin);
+        }
+        if is_string(&name) || is_number(&name) || is_blank(&name) {
+            self.literals.insert(name.clone());
+        }
         self.variables.insert(name, value);
     }
+
+    /// Whether the literal written as `token` was assigned to.
+    fn redefined_literal(&self, token: &str) -> bool {
+        !self.literals.is_empty() && self.literals.contains(token)
+    }
+
+    /// Push the value of a variable not holding a block, or call the native
+    /// builtin of that name if no variable hides it.
+    fn push_variable(&mut self, name: &str) -> Result<(), GSError> {
+        if !self.variables.contains_key(name) {
+            if let Some(native) = self.natives.get(name) {
+                let native = Rc::clone(native);
+                return native(&mut self.stack);
+            }
+        }
+        let value = self.get_variable(name)?;
+        self.push(value);
+        Ok(())
+    }
+
     fn get_variable(&mut self, name: &str) -> Result<Item, GSError> {
         match self.variables.get(name) {
             Some(value) => Ok(value.clone()),
-            None => Err(GSError::Runtime(format!("variable '{}' not founded", name))),
+            None => Err(ErrorKind::UndefinedVariable(name.to_string()).into()),
         }
     }
 
-    fn fun_call(&mut self, block: &[Item]) -> Result<Vec<Item>, GSError> {
-        let prev_size = self.stack.len();
-        match self.exec_items(&block) {
-            Ok(_) => Ok(self.stack.drain(prev_size - 1..).collect::<Vec<Item>>()),
+    fn fun_call(&mut self, block: &[Op]) -> Result<Vec<Item>, GSError> {
+        // the block may consume more than the value it is called with
+        let prev_size = self.stack.len().saturating_sub(1);
+        match self.exec_ops(block) {
+            Ok(_) => {
+                let start = prev_size.min(self.stack.len());
+                let results = self.stack.items.drain(start..).collect::<Vec<Item>>();
+                self.stack.clamp_markers(start);
+                Ok(results)
+            }
             Err(err) => Err(err),
         }
     }
 
-    fn fun_call_with(&mut self, block: &[Item], val: Item) -> Result<Vec<Item>, GSError> {
+    fn fun_call_with(&mut self, block: &[Op], val: Item) -> Result<Vec<Item>, GSError> {
         self.push(val);
         self.fun_call(block)
     }
+
+    /// Call `block` with `val` and check the truthiness of its last result.
+    fn test_with(&mut self, block: &[Op], val: Item) -> Result<bool, GSError> {
+        Ok(self
+            .fun_call_with(block, val)?
+            .last()
+            .is_some_and(Item::is_true))
+    }
 }
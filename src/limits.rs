@@ -0,0 +1,54 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Bounds on the resources a program may use, `None` leaves a resource
+/// unbounded.
+///
+/// Without `max_depth` a deeply recursive program can overflow the native
+/// stack, so untrusted programs should always set it.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Limits {
+    /// Number of operations executed by a single call to `exec`,
+    /// `exec_items` or `run`.
+    pub max_ops: Option<u64>,
+    /// Wall-clock time taken by a single call to `exec`, `exec_items` or
+    /// `run`.
+    pub max_time: Option<Duration>,
+    /// Nesting of blocks being executed.
+    pub max_depth: Option<usize>,
+    /// Number of items on the stack.
+    pub max_stack: Option<usize>,
+    /// Size of a single item, see `Item::size`.
+    pub max_item_size: Option<usize>,
+    /// Approximate bytes allocated by the builtins building strings and
+    /// arrays during a single call to `exec`, `exec_items` or `run`.
+    ///
+    /// This bounds the total volume allocated, not the memory in use at a
+    /// given time: items that were dropped still count. Builtins fail
+    /// before allocating when they would go beyond it.
+    pub max_allocation: Option<usize>,
+}
+
+/// The limit that stopped a program.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Limit {
+    Ops,
+    Time,
+    Depth,
+    Stack,
+    ItemSize,
+    Allocation,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Ops => write!(f, "operation"),
+            Limit::Time => write!(f, "time"),
+            Limit::Depth => write!(f, "recursion depth"),
+            Limit::Stack => write!(f, "stack length"),
+            Limit::ItemSize => write!(f, "item size"),
+            Limit::Allocation => write!(f, "allocation"),
+        }
+    }
+}
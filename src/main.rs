@@ -1,8 +1,15 @@
 extern crate copperline;
 extern crate golfscript;
 
-fn main() {
-    let mut it = golfscript::Interpreter::new();
+use golfscript::Interpreter;
+use std::{env, fs, process};
+
+fn usage() -> ! {
+    eprintln!("usage: golfscript-frontend [--seed n] [script.gs | -e code]");
+    process::exit(2);
+}
+
+fn repl(mut it: Interpreter) {
     let mut rl = copperline::Copperline::new();
 
     while let Ok(line) = rl.read_line_utf8(">> ") {
@@ -16,8 +23,41 @@ fn main() {
             }
 
             Err(err) => {
-                println!("{:?}", err);
+                println!("{}", err);
             }
         }
     }
 }
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    let mut it = match args.iter().position(|arg| arg == "--seed") {
+        Some(i) => match args.get(i + 1).and_then(|seed| seed.parse().ok()) {
+            Some(seed) => {
+                args.drain(i..i + 2);
+                Interpreter::with_seed(seed)
+            }
+            None => usage(),
+        },
+        None => Interpreter::new(),
+    };
+
+    let code = match args.as_slice() {
+        [] => return repl(it),
+        [flag, code] if flag == "-e" => code.clone(),
+        [path] if !path.starts_with('-') => match fs::read_to_string(path) {
+            Ok(code) => code,
+            Err(err) => {
+                eprintln!("cannot read {}: {}", path, err);
+                process::exit(1);
+            }
+        },
+        _ => usage(),
+    };
+
+    if let Err(err) = it.run(&code) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
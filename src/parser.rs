@@ -0,0 +1,169 @@
+use items::{Code, Item};
+use lexer::is_blank;
+
+/// The operators and builtin functions of the language.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Builtin {
+    Add,
+    Sub,
+    Not,
+    At,
+    Dollar,
+    Mul,
+    Div,
+    Modulo,
+    Or,
+    And,
+    Xor,
+    Swap,
+    PopDiscard,
+    Lt,
+    Gt,
+    Eq,
+    Dup,
+    Qmark,
+    Dec,
+    Inc,
+    Marker,
+    Slice,
+    Neg,
+    Backtick,
+    Array,
+    Abs,
+    If,
+    Do,
+    While,
+    Until,
+    Rand,
+    Print,
+    Zip,
+    Base,
+}
+
+impl Builtin {
+    /// Resolve the name of a token into a builtin, if it is one.
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        Some(match name {
+            "+" => Builtin::Add,
+            "-" => Builtin::Sub,
+            "!" => Builtin::Not,
+            "@" => Builtin::At,
+            "$" => Builtin::Dollar,
+            "*" => Builtin::Mul,
+            "/" => Builtin::Div,
+            "%" => Builtin::Modulo,
+            "|" => Builtin::Or,
+            "&" => Builtin::And,
+            "^" => Builtin::Xor,
+            "\\" => Builtin::Swap,
+            ";" => Builtin::PopDiscard,
+            "<" => Builtin::Lt,
+            ">" => Builtin::Gt,
+            "=" => Builtin::Eq,
+            "." => Builtin::Dup,
+            "?" => Builtin::Qmark,
+            "(" => Builtin::Dec,
+            ")" => Builtin::Inc,
+            "[" => Builtin::Marker,
+            "]" => Builtin::Slice,
+            "~" => Builtin::Neg,
+            "`" => Builtin::Backtick,
+            "," => Builtin::Array,
+            "abs" => Builtin::Abs,
+            "if" => Builtin::If,
+            "do" => Builtin::Do,
+            "while" => Builtin::While,
+            "until" => Builtin::Until,
+            "rand" => Builtin::Rand,
+            "print" => Builtin::Print,
+            "zip" => Builtin::Zip,
+            "base" => Builtin::Base,
+            _ => return None,
+        })
+    }
+
+    /// The token naming the builtin.
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Add => "+",
+            Builtin::Sub => "-",
+            Builtin::Not => "!",
+            Builtin::At => "@",
+            Builtin::Dollar => "$",
+            Builtin::Mul => "*",
+            Builtin::Div => "/",
+            Builtin::Modulo => "%",
+            Builtin::Or => "|",
+            Builtin::And => "&",
+            Builtin::Xor => "^",
+            Builtin::Swap => "\\",
+            Builtin::PopDiscard => ";",
+            Builtin::Lt => "<",
+            Builtin::Gt => ">",
+            Builtin::Eq => "=",
+            Builtin::Dup => ".",
+            Builtin::Qmark => "?",
+            Builtin::Dec => "(",
+            Builtin::Inc => ")",
+            Builtin::Marker => "[",
+            Builtin::Slice => "]",
+            Builtin::Neg => "~",
+            Builtin::Backtick => "`",
+            Builtin::Array => ",",
+            Builtin::Abs => "abs",
+            Builtin::If => "if",
+            Builtin::Do => "do",
+            Builtin::While => "while",
+            Builtin::Until => "until",
+            Builtin::Rand => "rand",
+            Builtin::Print => "print",
+            Builtin::Zip => "zip",
+            Builtin::Base => "base",
+        }
+    }
+}
+
+/// A single executable instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    /// Push a number or string, unless its token was assigned to.
+    Literal(Item, String),
+    /// Push an array or block.
+    Push(Item),
+    /// Store the top of the stack in a variable.
+    Assign(String),
+    /// Call a builtin, unless the user redefined its name.
+    Builtin(Builtin),
+    /// Look up a user variable.
+    Var(String),
+    /// A whitespace or comment, which runs only once assigned to.
+    Blank(String),
+}
+
+/// Resolve a single item into an `Op`.
+///
+/// `token` is the source text of the item, literals built without source
+/// are named after their inspect form.
+pub fn parse_item(item: &Item, token: Option<&str>) -> Op {
+    match item {
+        Item::Var(name) if is_blank(name) => Op::Blank(name.clone()),
+        Item::Var(name) => match Builtin::from_name(name) {
+            Some(builtin) => Op::Builtin(builtin),
+            None => Op::Var(name.clone()),
+        },
+        Item::Assign(name) => Op::Assign(name.clone()),
+        x @ Item::Num(_) | x @ Item::Str(_) => {
+            let token = token.map_or_else(|| x.to_string(), str::to_string);
+            Op::Literal(x.clone(), token)
+        }
+        x => Op::Push(x.clone()),
+    }
+}
+
+/// Resolve the body of a block into `Op`s.
+pub fn parse(code: &Code) -> Box<[Op]> {
+    code.iter()
+        .enumerate()
+        .map(|(i, item)| parse_item(item, code.token(i)))
+        .collect()
+}
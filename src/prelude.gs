@@ -0,0 +1,7 @@
+# The functions the reference interpreter defines in GolfScript itself
+"\n":n;
+{print n print}:puts;
+{`puts}:p;
+{1$if}:and;
+{1$\if}:or;
+{\!!{!}*}:xor;
@@ -0,0 +1,89 @@
+use std::ops::{Deref, DerefMut};
+
+use convert::FromItem;
+use items::{ErrorKind, GSError, Item};
+
+/// The stack of values a program works on, along with the positions
+/// marked by `[`.
+///
+/// It derefs to the slice of its items, from the bottom to the top.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stack {
+    pub(crate) items: Vec<Item>,
+
+    /// Store all past stack markers
+    pub(crate) markers: Vec<usize>,
+}
+
+impl Stack {
+    /// Create an empty stack.
+    pub fn new() -> Self {
+        Stack::default()
+    }
+
+    /// Push a value on top of the stack.
+    pub fn push(&mut self, value: Item) {
+        self.items.push(value)
+    }
+
+    /// Pop a single value off the stack.
+    pub fn pop(&mut self) -> Result<Item, GSError> {
+        match self.items.pop() {
+            Some(value) => {
+                // Resize all markers that are beyond the edge of the stack
+                let len = self.items.len();
+                self.clamp_markers(len);
+                Ok(value)
+            }
+
+            None => Err(ErrorKind::StackUnderflow.into()),
+        }
+    }
+
+    /// Pop a single value and convert it to a Rust type.
+    pub fn pop_as<T: FromItem>(&mut self) -> Result<T, GSError> {
+        T::from_item(self.pop()?)
+    }
+
+    /// Pop the two top values, the topmost first.
+    pub fn pop2(&mut self) -> Result<(Item, Item), GSError> {
+        Ok((self.pop()?, self.pop()?))
+    }
+
+    /// The value on top of the stack.
+    pub fn peek(&self) -> Result<&Item, GSError> {
+        match self.items.last() {
+            Some(value) => Ok(value),
+            None => Err(ErrorKind::StackUnderflow.into()),
+        }
+    }
+
+    /// Remove every value and marker.
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.markers.clear();
+    }
+
+    /// Resize the markers beyond `len`, as popping down to `len` would.
+    pub(crate) fn clamp_markers(&mut self, len: usize) {
+        for marker in self.markers.iter_mut() {
+            if *marker > len {
+                *marker = len;
+            }
+        }
+    }
+}
+
+impl Deref for Stack {
+    type Target = [Item];
+
+    fn deref(&self) -> &[Item] {
+        &self.items
+    }
+}
+
+impl DerefMut for Stack {
+    fn deref_mut(&mut self) -> &mut [Item] {
+        &mut self.items
+    }
+}
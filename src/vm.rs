@@ -0,0 +1,410 @@
+use bultins::{char_code, ITEM_SIZE};
+use items::{Code, GSError, IntoVec, Item, Span};
+use lexer::is_blank;
+use num_bigint::BigInt;
+use parser::Builtin;
+use Interpreter;
+use Item::*;
+
+/// A single bytecode instruction.
+///
+/// Operands index into the tables of the `Program` holding the instruction.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Instr {
+    /// Push a copy of a constant.
+    Const(usize),
+    /// Push a number or string constant, unless the name of its token was
+    /// assigned to.
+    Literal(usize, usize),
+    /// Call a builtin, unless the user redefined its name.
+    Builtin(Builtin),
+    /// Look up a variable by name.
+    Var(usize),
+    /// A whitespace or comment, running the variable of that name if it
+    /// was assigned to.
+    Blank(usize),
+    /// Store the top of the stack in a variable.
+    Assign(usize),
+    /// A block literal followed by `%`, mapping it over the top of the stack.
+    Map(usize),
+    /// A block literal followed by `/`, running it for each element.
+    Each(usize),
+    /// A block literal followed by `*`, folding the top of the stack.
+    Fold(usize),
+    /// A block literal followed by `do`.
+    Do(usize),
+}
+
+/// A block literal fused with the operator that consumes it.
+#[derive(Debug)]
+struct FusedBlock {
+    /// The block, pushed when the operator cannot take the fast path.
+    item: Item,
+    /// The compiled body of the block.
+    chunk: usize,
+}
+
+/// Compiled code, the program itself is the first chunk.
+#[derive(Debug, Default)]
+pub struct Program {
+    chunks: Vec<Box<[Instr]>>,
+    consts: Vec<Item>,
+    names: Vec<String>,
+    blocks: Vec<FusedBlock>,
+    /// Source position of each instruction of the first chunk, if known.
+    spans: Vec<Span>,
+}
+
+// The source text of each item of a block, if it is a literal.
+fn code_tokens(code: &Code) -> Vec<Option<&str>> {
+    (0..code.len()).map(|i| code.token(i)).collect()
+}
+
+impl Program {
+    /// Compile a sequence of items.
+    pub fn compile(code: &Code) -> Program {
+        let mut prog = Program::default();
+        prog.chunk(code, &code_tokens(code), None);
+        prog
+    }
+
+    /// Compile the top-level items of `input` keeping their positions for
+    /// error reporting.
+    pub fn compile_spanned(input: &str, tokens: &[(Item, Span)]) -> Program {
+        let (items, spans): (Vec<_>, Vec<_>) = tokens.iter().cloned().unzip();
+        let texts: Vec<_> = spans
+            .iter()
+            .map(|span| Some(&input[span.start..span.end]))
+            .collect();
+        let mut prog = Program::default();
+        prog.chunk(&items, &texts, Some(&spans));
+        prog
+    }
+
+    // Compile `items` into a new chunk, returning its index. `tokens` holds
+    // the source text of each item, if known.
+    fn chunk(&mut self, items: &[Item], tokens: &[Option<&str>], spans: Option<&[Span]>) -> usize {
+        let id = self.chunks.len();
+        // reserve the index before compiling nested blocks
+        self.chunks.push(Box::new([]));
+
+        let mut code = Vec::with_capacity(items.len());
+        let mut i = 0;
+        while i < items.len() {
+            let fused = match (&items[i], items.get(i + 1)) {
+                (Block(body), Some(Var(op))) => {
+                    let instr: Option<fn(usize) -> Instr> = match op.as_str() {
+                        "%" => Some(Instr::Map),
+                        "/" => Some(Instr::Each),
+                        "*" => Some(Instr::Fold),
+                        "do" => Some(Instr::Do),
+                        _ => None,
+                    };
+                    instr.map(|instr| {
+                        let chunk = self.chunk(body, &code_tokens(body), None);
+                        self.blocks.push(FusedBlock {
+                            item: items[i].clone(),
+                            chunk,
+                        });
+                        instr(self.blocks.len() - 1)
+                    })
+                }
+                _ => None,
+            };
+
+            let (instr, len) = match fused {
+                Some(instr) => (instr, 2),
+                None => (self.instr(&items[i], tokens[i]), 1),
+            };
+            code.push(instr);
+            // pushing a block cannot fail, so fused instructions take the
+            // position of their operator
+            if let Some(spans) = spans {
+                self.spans.push(spans[i + len - 1]);
+            }
+            i += len;
+        }
+
+        self.chunks[id] = code.into_boxed_slice();
+        id
+    }
+
+    fn instr(&mut self, item: &Item, token: Option<&str>) -> Instr {
+        match item {
+            Var(name) if is_blank(name) => Instr::Blank(self.name(name)),
+            Var(name) => match Builtin::from_name(name) {
+                Some(builtin) => Instr::Builtin(builtin),
+                None => Instr::Var(self.name(name)),
+            },
+            Assign(name) => Instr::Assign(self.name(name)),
+            x @ Num(_) | x @ Str(_) => {
+                let name = match token {
+                    Some(token) => self.name(token),
+                    None => self.name(&x.to_string()),
+                };
+                self.consts.push(x.clone());
+                Instr::Literal(self.consts.len() - 1, name)
+            }
+            x => {
+                self.consts.push(x.clone());
+                Instr::Const(self.consts.len() - 1)
+            }
+        }
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        match self.names.iter().position(|n| n == name) {
+            Some(i) => i,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            }
+        }
+    }
+}
+
+impl Interpreter {
+    /// Run a compiled program, errors point to the failing instruction.
+    pub(crate) fn run_program(&mut self, prog: &Program) -> Result<(), GSError> {
+        for (pc, instr) in prog.chunks[0].iter().enumerate() {
+            if let Err(err) = self.run_instr(prog, *instr) {
+                return Err(match prog.spans.get(pc) {
+                    Some(&span) => err.with_span(span),
+                    None => err,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn run_chunk(&mut self, prog: &Program, chunk: usize) -> Result<(), GSError> {
+        self.enter()?;
+        let res = prog.chunks[chunk]
+            .iter()
+            .try_for_each(|instr| self.run_instr(prog, *instr));
+        self.depth -= 1;
+        res
+    }
+
+    fn run_instr(&mut self, prog: &Program, instr: Instr) -> Result<(), GSError> {
+        if let Instr::Blank(name) = instr {
+            if !self.redefined_literal(&prog.names[name]) {
+                return Ok(());
+            }
+        }
+        self.step()?;
+        match instr {
+            Instr::Literal(_, name) if self.redefined_literal(&prog.names[name]) => {
+                self.run_variable(&prog.names[name])?
+            }
+            Instr::Literal(i, _) | Instr::Const(i) => self.push(prog.consts[i].clone()),
+            Instr::Builtin(builtin) if self.shadowed.contains(&builtin) => {
+                self.run_variable(builtin.name())?
+            }
+            Instr::Builtin(builtin) => {
+                if !self.run_num_fast(builtin) {
+                    self.exec_builtin(builtin)?
+                }
+            }
+            Instr::Var(i) | Instr::Blank(i) => self.run_variable(&prog.names[i])?,
+            Instr::Assign(i) => self.assign(prog.names[i].clone())?,
+            Instr::Map(i) => self.run_map(prog, &prog.blocks[i])?,
+            Instr::Each(i) => self.run_each(prog, &prog.blocks[i])?,
+            Instr::Fold(i) => self.run_fold(prog, &prog.blocks[i])?,
+            Instr::Do(i) => self.run_do(prog, &prog.blocks[i])?,
+        }
+        self.check_stack()
+    }
+
+    /// Apply common builtins in place when their operands are numbers,
+    /// returning whether the builtin was handled.
+    fn run_num_fast(&mut self, builtin: Builtin) -> bool {
+        let len = self.stack.len();
+        match builtin {
+            Builtin::Dup => match self.stack.last() {
+                Some(Num(x)) => {
+                    let x = Num(x.clone());
+                    // `.` pops the value before pushing it twice
+                    self.stack.clamp_markers(len - 1);
+                    self.stack.push(x);
+                }
+                _ => return false,
+            },
+
+            Builtin::Inc | Builtin::Dec => {
+                match self.stack.last_mut() {
+                    Some(Num(x)) if builtin == Builtin::Inc => *x += 1,
+                    Some(Num(x)) => *x -= 1,
+                    _ => return false,
+                }
+                self.stack.clamp_markers(len - 1);
+            }
+
+            Builtin::Add
+            | Builtin::Sub
+            | Builtin::Mul
+            | Builtin::Lt
+            | Builtin::Gt
+            | Builtin::Eq
+                if len >= 2 =>
+            {
+                let (below, top) = self.stack.split_at_mut(len - 1);
+                let (x, y) = match (&mut below[len - 2], &top[0]) {
+                    (Num(x), Num(y)) => (x, y),
+                    _ => return false,
+                };
+                match builtin {
+                    Builtin::Add => *x += y,
+                    Builtin::Sub => *x -= y,
+                    Builtin::Mul => *x *= y,
+                    Builtin::Lt => *x = BigInt::from((*x < *y) as u8),
+                    Builtin::Gt => *x = BigInt::from((*x > *y) as u8),
+                    _ => *x = BigInt::from((*x == *y) as u8),
+                }
+                self.stack.items.pop();
+                self.stack.clamp_markers(len - 2);
+            }
+
+            _ => return false,
+        }
+        true
+    }
+
+    /// Execute a variable, compiling its block on the first call.
+    fn run_variable(&mut self, name: &str) -> Result<(), GSError> {
+        let prog = match self.variables.get(name) {
+            Some(Block(code)) => code.program(),
+            _ => return self.push_variable(name),
+        };
+        self.run_chunk(&prog, 0)
+    }
+
+    /// Fall back to the builtin when the operand is not an array or string
+    /// or the operator was redefined.
+    fn fused_fallback(
+        &mut self,
+        builtin: Builtin,
+        block: &FusedBlock,
+        value: Option<Item>,
+    ) -> Result<(), GSError> {
+        if let Some(value) = value {
+            self.push(value);
+        }
+        self.push(block.item.clone());
+        if self.shadowed.contains(&builtin) {
+            self.run_variable(builtin.name())
+        } else {
+            self.exec_builtin(builtin)
+        }
+    }
+
+    // Run the block on `val` and move the values it leaves into `out`.
+    fn map_one(
+        &mut self,
+        prog: &Program,
+        chunk: usize,
+        val: Item,
+        out: &mut Vec<Item>,
+    ) -> Result<(), GSError> {
+        // the block may consume more than the value it is called with
+        let mark = self.stack.len();
+        self.push(val);
+        self.run_chunk(prog, chunk)?;
+        let start = mark.min(self.stack.len());
+        let len = self.stack.len() - start;
+        self.allocate(len, ITEM_SIZE)?;
+        out.extend(self.stack.items.drain(start..));
+        self.stack.clamp_markers(start);
+        Ok(())
+    }
+
+    fn run_map(&mut self, prog: &Program, block: &FusedBlock) -> Result<(), GSError> {
+        if self.shadowed.contains(&Builtin::Modulo) {
+            return self.fused_fallback(Builtin::Modulo, block, None);
+        }
+        match self.pop()? {
+            Array(x) => {
+                let mut items = Vec::with_capacity(x.len());
+                for el in x.into_vec() {
+                    self.map_one(prog, block.chunk, el, &mut items)?;
+                }
+                self.push(Array(items.into()));
+            }
+            Str(x) => {
+                let mut items = Vec::with_capacity(x.len());
+                for c in x.chars() {
+                    self.map_one(prog, block.chunk, char_code(c), &mut items)?;
+                }
+                match Array(items.into()).upcast_to_string() {
+                    Some(res) => self.push(res),
+                    None => return Err(self.type_error("%", &["string", "block"])),
+                }
+            }
+            x => return self.fused_fallback(Builtin::Modulo, block, Some(x)),
+        }
+        Ok(())
+    }
+
+    fn run_each(&mut self, prog: &Program, block: &FusedBlock) -> Result<(), GSError> {
+        if self.shadowed.contains(&Builtin::Div) {
+            return self.fused_fallback(Builtin::Div, block, None);
+        }
+        match self.pop()? {
+            Array(x) => {
+                for el in x.into_vec() {
+                    self.push(el);
+                    self.run_chunk(prog, block.chunk)?;
+                }
+            }
+            Str(x) => {
+                for c in x.chars() {
+                    self.push(char_code(c));
+                    self.run_chunk(prog, block.chunk)?;
+                }
+            }
+            x => return self.fused_fallback(Builtin::Div, block, Some(x)),
+        }
+        Ok(())
+    }
+
+    fn run_fold(&mut self, prog: &Program, block: &FusedBlock) -> Result<(), GSError> {
+        if self.shadowed.contains(&Builtin::Mul) {
+            return self.fused_fallback(Builtin::Mul, block, None);
+        }
+        let len = match self.pop()? {
+            Array(x) => {
+                let len = x.len();
+                for el in x.into_vec() {
+                    self.push(el);
+                }
+                len
+            }
+            Str(x) => {
+                let len = x.chars().count();
+                for c in x.chars() {
+                    self.push(char_code(c));
+                }
+                len
+            }
+            x => return self.fused_fallback(Builtin::Mul, block, Some(x)),
+        };
+        for _ in 1..len {
+            self.run_chunk(prog, block.chunk)?;
+        }
+        Ok(())
+    }
+
+    fn run_do(&mut self, prog: &Program, block: &FusedBlock) -> Result<(), GSError> {
+        if self.shadowed.contains(&Builtin::Do) {
+            return self.fused_fallback(Builtin::Do, block, None);
+        }
+        loop {
+            // the condition is left on the stack by the body
+            self.run_chunk(prog, block.chunk)?;
+            if !self.pop()?.is_true() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,31 @@
 #![allow(dead_code)]
 
 extern crate golfscript;
-
-use golfscript::{GSError, Interpreter, Item};
+extern crate rand;
+
+use golfscript::{
+    BigInt, Engine, ErrorKind, FromItem, GSError, Interpreter, IntoItem, Item, Limit, Limits, Span,
+    Stack,
+};
+use rand::rngs::mock::StepRng;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::Duration;
 
 use Item::*;
 
 // Helper macros for initializing items
+macro_rules! Num {
+    ($x:expr) => {{
+        Num(BigInt::from($x))
+    }};
+}
+
 macro_rules! Array {
     ($x:expr) => {{
-        Array(Box::new($x))
+        Array(Rc::new($x.to_vec()))
     }};
 }
 
@@ -21,38 +37,75 @@ macro_rules! Str {
 
 macro_rules! Block {
     ($x:expr) => {{
-        Block(Box::new($x))
+        Block($x.to_vec().into())
     }};
 }
 
 fn eval_(input: &str) -> Result<Vec<Item>, GSError> {
     let mut it = Interpreter::new();
-    it.exec(&input).map(|x| x.to_vec())
+    it.exec(input).map(|x| x.to_vec())
 }
 
 fn eval(input: &str) -> Vec<Item> {
-    eval_(&input).unwrap()
+    eval_(input).unwrap()
+}
+
+fn eval_err(input: &str) -> ErrorKind {
+    eval_(input).unwrap_err().kind
+}
+
+/// Output handle that can still be read after moving it into an interpreter.
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Execute `input` with the given stdin, returning everything printed.
+fn exec_with_stdin(input: &str, stdin: &str) -> String {
+    let out = SharedBuf::default();
+    let mut it = Interpreter::with_io(io::Cursor::new(stdin.to_string()), out.clone());
+    it.exec(input).unwrap();
+    let res = String::from_utf8(out.0.borrow().clone()).unwrap();
+    res
+}
+
+/// Run `input` as a whole program with the given stdin, returning everything
+/// printed.
+fn run_with_stdin(input: &str, stdin: &str) -> String {
+    let out = SharedBuf::default();
+    let mut it = Interpreter::with_io(io::Cursor::new(stdin.to_string()), out.clone());
+    it.run(input).unwrap();
+    let res = String::from_utf8(out.0.borrow().clone()).unwrap();
+    res
 }
 
 // test~
 #[test]
 fn negate_num() {
-    assert_eq!(eval("5~"), [Num(-6)])
+    assert_eq!(eval("5~"), [Num!(-6)])
 }
 
 #[test]
 fn negate_str() {
-    assert_eq!(eval("\"1 2+\"~"), [Num(3)]);
+    assert_eq!(eval("\"1 2+\"~"), [Num!(3)]);
 }
 
 #[test]
 fn negate_block() {
-    assert_eq!(eval("{1 2+}~"), [Num(3)]);
+    assert_eq!(eval("{1 2+}~"), [Num!(3)]);
 }
 
 #[test]
 fn negate_array() {
-    assert_eq!(eval("[1 2 3]~"), [Num(1), Num(2), Num(3)]);
+    assert_eq!(eval("[1 2 3]~"), [Num!(1), Num!(2), Num!(3)]);
 }
 
 // test`
@@ -74,43 +127,61 @@ fn backtick_str() {
 #[test]
 fn backtick_block() {
     assert_eq!(eval("{1}`"), [Str!("{1}")]);
+    // blocks are shown as they were written
+    assert_eq!(eval("{.;}`"), [Str!("{.;}")]);
+    assert_eq!(eval("{ 1 #x\n}`"), [Str!("{ 1 #x\n}")]);
+    assert_eq!(eval("{1}{2-}+`"), [Str!("{1 2-}")]);
+}
+
+// test output form
+fn output(input: &str) -> String {
+    eval(input).iter().map(Item::output).collect()
+}
+
+#[test]
+fn output_form() {
+    assert_eq!(output("-12"), "-12");
+    assert_eq!(output("\"a\nb\""), "a\nb");
+    assert_eq!(output("[1 [2 [\"x\"]] \"yz\"]"), "12xyz");
+    assert_eq!(output("{1 \"a\" +}"), "{1 \"a\" +}");
+    assert_eq!(output("1 \"a\" [] {}"), "1a{}");
 }
 
 // test!
 #[test]
 fn exclaim_num() {
-    assert_eq!(eval("0!"), [Num(1)]);
-    assert_eq!(eval("1!"), [Num(0)]);
+    assert_eq!(eval("0!"), [Num!(1)]);
+    assert_eq!(eval("1!"), [Num!(0)]);
 }
 
 #[test]
 fn exclaim_array() {
-    assert_eq!(eval("[]!"), [Num(1)]);
-    assert_eq!(eval("[1 4]!"), [Num(0)]);
+    assert_eq!(eval("[]!"), [Num!(1)]);
+    assert_eq!(eval("[1 4]!"), [Num!(0)]);
 }
 
 #[test]
 fn exclaim_str() {
-    assert_eq!(eval("\"\"!"), [Num(1)]);
-    assert_eq!(eval("\"asdf\"!"), [Num(0)]);
+    assert_eq!(eval("\"\"!"), [Num!(1)]);
+    assert_eq!(eval("\"asdf\"!"), [Num!(0)]);
 }
 
 #[test]
 fn exclaim_block() {
-    assert_eq!(eval("{}!"), [Num(1)]);
-    assert_eq!(eval("{5}!"), [Num(0)]);
+    assert_eq!(eval("{}!"), [Num!(1)]);
+    assert_eq!(eval("{5}!"), [Num!(0)]);
 }
 
 // test@
 #[test]
 fn at() {
-    assert_eq!(eval("1 2 3 4 @"), [Num(1), Num(3), Num(4), Num(2)]);
+    assert_eq!(eval("1 2 3 4 @"), [Num!(1), Num!(3), Num!(4), Num!(2)]);
 }
 
 // test#
 #[test]
 fn hash() {
-    assert_eq!(eval("1 # Here is a comment"), [Num(1)]);
+    assert_eq!(eval("1 # Here is a comment"), [Num!(1)]);
 }
 
 // test$
@@ -118,7 +189,7 @@ fn hash() {
 fn dollar_num() {
     assert_eq!(
         eval("1 2 3 4 5 1$"),
-        [Num(1), Num(2), Num(3), Num(4), Num(5), Num(4)]
+        [Num!(1), Num!(2), Num!(3), Num!(4), Num!(5), Num!(4)]
     );
 }
 
@@ -131,7 +202,7 @@ fn dollar_str() {
 fn dollar_array() {
     assert_eq!(
         eval("[5 4 3 1 2]$"),
-        [Array!([Num(1), Num(2), Num(3), Num(4), Num(5)])]
+        [Array!([Num!(1), Num!(2), Num!(3), Num!(4), Num!(5)])]
     );
     assert_eq!(
         eval("[\"ccc\" \"bbb\" \"aaa\"]$"),
@@ -143,15 +214,17 @@ fn dollar_array() {
 fn dollar_block() {
     assert_eq!(
         eval("[5 4 3 1 2]{-1*}$"),
-        [Array!([Num(5), Num(4), Num(3), Num(2), Num(1)])]
+        [Array!([Num!(5), Num!(4), Num!(3), Num!(2), Num!(1)])]
     );
-    assert_eq!(eval("\"asdf\"{\"\"+}$"), [Str!("adfs")]);
+    assert_eq!(eval("\"asdf\"{-1*}$"), [Str!("sfda")]);
+    // the block is given the character codes
+    assert_eq!(eval("\"asdf\"{\"\"+}$"), [Str!("dfsa")]);
 }
 
 // test+
 #[test]
 fn add_num() {
-    assert_eq!(eval("5 7+"), [Num(12)]);
+    assert_eq!(eval("5 7+"), [Num!(12)]);
 }
 
 #[test]
@@ -161,71 +234,88 @@ fn add_str() {
 
 #[test]
 fn add_array() {
-    assert_eq!(eval("[1][2]+"), [Array!([Num(1), Num(2)])]);
+    assert_eq!(eval("[1][2]+"), [Array!([Num!(1), Num!(2)])]);
 }
 
 #[test]
 fn add_block() {
     assert_eq!(
         eval("{1}{2-}+"),
-        [Block!([Num(1), Num(2), Var("-".to_string())])]
+        [Block!([Num!(1), Num!(2), Var("-".to_string())])]
     );
 }
 
 #[test]
 fn add_coercion() {
     // to block
-    assert_eq!(eval("\"a\"{2}+"), [Block!([Str!("a"), Num(2)])]);
-    assert_eq!(eval("[1 2]{2}+"), [Block!([Num(1), Num(2), Num(2)])]);
-    assert_eq!(eval("1{2}+"), [Block!([Num(1), Num(2)])]);
+    assert_eq!(eval("\"a\"{2}+"), [Block!([Str!("a"), Num!(2)])]);
+    assert_eq!(eval("[1 2]{2}+"), [Block!([Num!(1), Num!(2), Num!(2)])]);
+    assert_eq!(eval("1{2}+"), [Block!([Num!(1), Num!(2)])]);
 
     // to string
     assert_eq!(eval("[50]\"b\"+"), [Str!("2b")]);
     assert_eq!(eval("1\"b\"+"), [Str!("1b")]);
 
     // to array
-    assert_eq!(eval("1[2]+"), [Array!([Num(1), Num(2)])]);
+    assert_eq!(eval("1[2]+"), [Array!([Num!(1), Num!(2)])]);
 }
 
 // test-
 #[test]
 fn sub_num() {
-    assert_eq!(eval("-1"), [Num(-1)]);
-    assert_eq!(eval("1 2-3+"), [Num(1), Num(-1)]);
-    assert_eq!(eval("1 2 -3+"), [Num(1), Num(-1)]);
-    assert_eq!(eval("1 2- 3+"), [Num(2)]);
+    assert_eq!(eval("-1"), [Num!(-1)]);
+    assert_eq!(eval("1 2-3+"), [Num!(1), Num!(-1)]);
+    assert_eq!(eval("1 2 -3+"), [Num!(1), Num!(-1)]);
+    assert_eq!(eval("1 2- 3+"), [Num!(2)]);
 }
 
 #[test]
 fn sub_array() {
     assert_eq!(
         eval("[5 2 5 4 1 1][1 2]-"),
-        [Array!([Num(5), Num(5), Num(4)])]
+        [Array!([Num!(5), Num!(5), Num!(4)])]
     );
 }
 
 #[test]
 fn sub_coercion() {
-    assert_eq!(eval("[1 2 3]2-"), [Array!([Num(1), Num(3)])]);
+    assert_eq!(eval("[1 2 3]2-"), [Array!([Num!(1), Num!(3)])]);
+}
+
+#[test]
+fn sub_str() {
+    assert_eq!(eval("\"abcb\"\"b\"-"), [Str!("ac")]);
 }
 
 // test*
 #[test]
 fn mul_num() {
-    assert_eq!(eval("2 4*"), [Num(8)]);
-    assert_eq!(eval("4 2*"), [Num(8)]);
+    assert_eq!(eval("2 4*"), [Num!(8)]);
+    assert_eq!(eval("4 2*"), [Num!(8)]);
+    assert_eq!(
+        eval("9223372036854775807 2*"),
+        [Num!(
+            BigInt::parse_bytes(b"18446744073709551614", 10).unwrap()
+        )]
+    );
 }
 
 #[test]
 fn mul_num_block() {
-    assert_eq!(eval("2 {2*} 5*"), [Num(64)]);
-    assert_eq!(eval("2 5 {2*}*"), [Num(64)]);
+    assert_eq!(eval("2 {2*} 5*"), [Num!(64)]);
+    assert_eq!(eval("2 5 {2*}*"), [Num!(64)]);
 }
 
 #[test]
 fn mul_num_array() {
-    assert_eq!(eval("[1 2] 2*"), [Array!([Num(1), Num(2), Num(1), Num(2)])]);
-    assert_eq!(eval("2 [1 2]*"), [Array!([Num(1), Num(2), Num(1), Num(2)])]);
+    assert_eq!(
+        eval("[1 2] 2*"),
+        [Array!([Num!(1), Num!(2), Num!(1), Num!(2)])]
+    );
+    assert_eq!(
+        eval("2 [1 2]*"),
+        [Array!([Num!(1), Num!(2), Num!(1), Num!(2)])]
+    );
 }
 
 #[test]
@@ -250,18 +340,18 @@ fn mul_join_array_str() {
 
 #[test]
 fn mul_join_array_array() {
-    assert_eq!(eval("[1 2][4]*"), [Array!([Num(1), Num(4), Num(2)])]);
+    assert_eq!(eval("[1 2][4]*"), [Array!([Num!(1), Num!(4), Num!(2)])]);
     assert_eq!(
         eval("[1 [2] [3 [4 [5]]]] [6 7]*"),
         [Array!([
-            Num(1),
-            Num(6),
-            Num(7),
-            Num(2),
-            Num(6),
-            Num(7),
-            Num(3),
-            Array!([Num(4), Array!([Num(5)])])
+            Num!(1),
+            Num!(6),
+            Num!(7),
+            Num!(2),
+            Num!(6),
+            Num!(7),
+            Num!(3),
+            Array!([Num!(4), Array!([Num!(5)])])
         ])]
     );
 }
@@ -273,20 +363,21 @@ fn mul_join_str_str() {
 
 #[test]
 fn mul_fold_array() {
-    assert_eq!(eval("[1 2 3 4]{+}*"), [Num(10)]);
-    assert_eq!(eval("{+}[1 2 3 4]*"), [Num(10)]);
+    assert_eq!(eval("[1 2 3 4]{+}*"), [Num!(10)]);
+    assert_eq!(eval("{+}[1 2 3 4]*"), [Num!(10)]);
 }
 
 #[test]
 fn mul_fold_str() {
-    assert_eq!(eval("\"asdf\"{+}*"), [Num(414)]);
-    assert_eq!(eval("{+}\"asdf\"*"), [Num(414)]);
+    assert_eq!(eval("\"asdf\"{+}*"), [Num!(414)]);
+    assert_eq!(eval("{+}\"asdf\"*"), [Num!(414)]);
 }
 
 // test/
 #[test]
 fn div_num() {
-    assert_eq!(eval("7 3/"), [Num(2)]);
+    assert_eq!(eval("7 3/"), [Num!(2)]);
+    assert_eq!(eval("-7 2/"), [Num!(-4)]);
 }
 
 #[test]
@@ -294,9 +385,9 @@ fn div_split_array() {
     assert_eq!(
         eval("[1 2 3 4 2 3 5][2 3]/"),
         [Array!([
-            Array!([Num(1)]),
-            Array!([Num(4)]),
-            Array!([Num(5)])
+            Array!([Num!(1)]),
+            Array!([Num!(4)]),
+            Array!([Num!(5)])
         ])]
     );
 }
@@ -314,9 +405,9 @@ fn div_chunk() {
     assert_eq!(
         eval("[1 2 3 4 5]2/"),
         [Array!([
-            Array!([Num(1), Num(2)]),
-            Array!([Num(3), Num(4)]),
-            Array!([Num(5)])
+            Array!([Num!(1), Num!(2)]),
+            Array!([Num!(3), Num!(4)]),
+            Array!([Num!(5)])
         ])]
     );
 }
@@ -326,21 +417,29 @@ fn div_unfold() {
     assert_eq!(
         eval("0 1 {10<}{.@+}/"),
         [
-            Num(8),
-            Array!([Num(1), Num(1), Num(2), Num(3), Num(5), Num(8)])
+            Num!(8),
+            Array!([Num!(1), Num!(1), Num!(2), Num!(3), Num!(5), Num!(8)])
         ]
     );
 }
 
 #[test]
 fn div_each() {
-    assert_eq!(eval("[1 2 3]{1+}/"), [Num(2), Num(3), Num(4)]);
+    assert_eq!(eval("[1 2 3]{1+}/"), [Num!(2), Num!(3), Num!(4)]);
+}
+
+#[test]
+fn div_each_str() {
+    assert_eq!(eval("\"ab\"{}/"), [Num!(97), Num!(98)]);
+    assert_eq!(eval("{1+}\"ab\"/"), [Num!(98), Num!(99)]);
+    assert_eq!(eval("{1+}[1 2]/"), [Num!(2), Num!(3)]);
 }
 
 // test%
 #[test]
 fn mod_num() {
-    assert_eq!(eval("7 3%"), [Num(1)]);
+    assert_eq!(eval("7 3%"), [Num!(1)]);
+    assert_eq!(eval("-7 2%"), [Num!(1)]);
 }
 
 #[test]
@@ -350,10 +449,13 @@ fn mod_split_str() {
 
 #[test]
 fn mod_array() {
-    assert_eq!(eval("[1 2 3 4 5] 2%"), [Array!([Num(1), Num(3), Num(5)])]);
+    assert_eq!(
+        eval("[1 2 3 4 5] 2%"),
+        [Array!([Num!(1), Num!(3), Num!(5)])]
+    );
     assert_eq!(
         eval("[1 2 3 4 5] -1%"),
-        [Array!([Num(5), Num(4), Num(3), Num(2), Num(1)])]
+        [Array!([Num!(5), Num!(4), Num!(3), Num!(2), Num!(1)])]
     );
 }
 
@@ -361,105 +463,160 @@ fn mod_array() {
 fn mod_map() {
     assert_eq!(
         eval("[1 2 3]{.}%"),
-        [Array!([Num(1), Num(1), Num(2), Num(2), Num(3), Num(3)])]
+        [Array!([
+            Num!(1),
+            Num!(1),
+            Num!(2),
+            Num!(2),
+            Num!(3),
+            Num!(3)
+        ])]
     );
 }
 
+#[test]
+fn mod_map_str() {
+    assert_eq!(eval("\"abc\"{)}%"), [Str!("bcd")]);
+    assert_eq!(eval("\"abc\"{.}%"), [Str!("aabbcc")]);
+    assert_eq!(eval("\"ab\"{;\"x\"}%"), [Str!("xx")]);
+    assert_eq!(eval("{)}\"abc\"%"), [Str!("bcd")]);
+    assert_eq!(eval("{.*}[1 2]%"), [Array!([Num!(1), Num!(4)])]);
+}
+
+#[test]
+fn mod_step_str() {
+    assert_eq!(eval("\"abcde\" 2%"), [Str!("ace")]);
+    assert_eq!(eval("\"abcd\" -2%"), [Str!("db")]);
+    assert_eq!(eval("[1 2 3 4] -2%"), [Array!([Num!(4), Num!(2)])]);
+}
+
 // test|
 #[test]
 fn or_num() {
-    assert_eq!(eval("5 3|"), [Num(7)]);
+    assert_eq!(eval("5 3|"), [Num!(7)]);
 }
 
 #[test]
 fn or_array() {
-    assert_eq!(eval("[1 1 2 2][1 3]|"), [Array!([Num(1), Num(2), Num(3)])]);
+    assert_eq!(
+        eval("[1 1 2 2][1 3]|"),
+        [Array!([Num!(1), Num!(2), Num!(3)])]
+    );
 }
 
 #[test]
 fn or_coercion() {
-    assert_eq!(eval("[1 1 2 2] 3 |"), [Array!([Num(1), Num(2), Num(3)])]);
+    assert_eq!(eval("[1 1 2 2] 3 |"), [Array!([Num!(1), Num!(2), Num!(3)])]);
+}
+
+#[test]
+fn or_str() {
+    assert_eq!(eval("\"abb\"\"bc\"|"), [Str!("abc")]);
 }
 
 // test&
 #[test]
 fn and_num() {
-    assert_eq!(eval("5 3&"), [Num(1)]);
+    assert_eq!(eval("5 3&"), [Num!(1)]);
 }
 
 #[test]
 fn and_array() {
-    assert_eq!(eval("[1 1 2 2][1 3]&"), [Array!([Num(1)])]);
+    assert_eq!(eval("[1 1 2 2][1 3]&"), [Array!([Num!(1)])]);
 }
 
 #[test]
 fn and_coercion() {
-    assert_eq!(eval("[1 1 2 2] 1 &"), [Array!([Num(1)])]);
+    assert_eq!(eval("[1 1 2 2] 1 &"), [Array!([Num!(1)])]);
+}
+
+#[test]
+fn and_str() {
+    assert_eq!(eval("\"abb\"\"bc\"&"), [Str!("b")]);
 }
 
 // test^
 #[test]
 fn xor_num() {
-    assert_eq!(eval("5 3^"), [Num(6)]);
+    assert_eq!(eval("5 3^"), [Num!(6)]);
 }
 
 // test^
 #[test]
 fn xor_array() {
-    assert_eq!(eval("[1 1 2 2][1 3]^"), [Array!([Num(2), Num(3)])]);
+    assert_eq!(eval("[1 1 2 2][1 3]^"), [Array!([Num!(2), Num!(3)])]);
+}
+
+#[test]
+fn xor_str() {
+    assert_eq!(eval("\"abb\"\"bc\"^"), [Str!("ac")]);
 }
 
 // test[]
 #[test]
 fn slice() {
-    assert_eq!(eval("[1 2]"), [Array!([Num(1), Num(2)])]);
-    assert_eq!(eval("1 2 [\\]"), [Array!([Num(2), Num(1)])]);
+    assert_eq!(eval("[1 2]"), [Array!([Num!(1), Num!(2)])]);
+    assert_eq!(eval("1 2 [\\]"), [Array!([Num!(2), Num!(1)])]);
 }
 
 // test\
 #[test]
 fn swap() {
-    assert_eq!(eval("1 2 3\\"), [Num(1), Num(3), Num(2)]);
+    assert_eq!(eval("1 2 3\\"), [Num!(1), Num!(3), Num!(2)]);
 }
 
 //test: (assign)
 #[test]
 fn assign() {
-    assert_eq!(eval("1:a a"), [Num(1), Num(1)]);
-    assert_eq!(eval("1:a;a"), [Num(1)]);
+    assert_eq!(eval("1:a a"), [Num!(1), Num!(1)]);
+    assert_eq!(eval("1:a;a"), [Num!(1)]);
     // TODO: activate this test, also number should be variable
-    // assert_eq!(eval("1:0;0"), [Num(1)]);
+    // assert_eq!(eval("1:0;0"), [Num!(1)]);
 }
 
 #[test]
 fn assign_block() {
-    assert_eq!(eval("{-1*-}:plus;3 2 plus"), [Num(5)])
+    assert_eq!(eval("{-1*-}:plus;3 2 plus"), [Num!(5)])
+}
+
+#[test]
+fn assign_redefine() {
+    assert_eq!(eval("{1}:f;{2}:f;f"), [Num!(2)]);
+    assert_eq!(eval("{1}:f;2:f;f"), [Num!(2)]);
+    assert_eq!(eval("{f}:g;{3}:f;g"), [Num!(3)]);
+}
+
+#[test]
+fn assign_builtin() {
+    assert_eq!(eval("{-}:+;5 3+"), [Num!(2)]);
+    assert_eq!(eval("{-}:+;[5 3]{+}*"), [Num!(2)]);
+    assert_eq!(eval("7:abs;abs"), [Num!(7)]);
 }
 
 // test;
 #[test]
 fn pop_discard() {
     assert_eq!(eval("1;"), []);
-    assert_eq!(eval("2 1;"), [Num(2)]);
+    assert_eq!(eval("2 1;"), [Num!(2)]);
 }
 
 // test<
 #[test]
 fn lt_num() {
-    assert_eq!(eval("3 4<"), [Num(1)]);
-    assert_eq!(eval("6 4<"), [Num(0)]);
+    assert_eq!(eval("3 4<"), [Num!(1)]);
+    assert_eq!(eval("6 4<"), [Num!(0)]);
 }
 
 #[test]
 fn lt_str() {
-    assert_eq!(eval("\"asdf\"\"asdg\"<"), [Num(1)]);
-    assert_eq!(eval("\"fdfg\"\"aaad\"<"), [Num(0)]);
+    assert_eq!(eval("\"asdf\"\"asdg\"<"), [Num!(1)]);
+    assert_eq!(eval("\"fdfg\"\"aaad\"<"), [Num!(0)]);
 }
 
 #[test]
 fn lt_array_num() {
-    assert_eq!(eval("[1 2 3]2<"), [Array!([Num(1), Num(2)])]);
-    assert_eq!(eval("[1 2 3]-2<"), [Array!([Num(1)])]);
+    assert_eq!(eval("[1 2 3]2<"), [Array!([Num!(1), Num!(2)])]);
+    assert_eq!(eval("[1 2 3]-2<"), [Array!([Num!(1)])]);
 }
 
 #[test]
@@ -468,6 +625,12 @@ fn lt_str_num() {
     assert_eq!(eval("\"asdf\" -1 <"), [Str!("asd")]);
 }
 
+#[test]
+fn lt_array() {
+    assert_eq!(eval("[1 2][1 3]<"), [Num!(1)]);
+    assert_eq!(eval("[1 3][1 2]<"), [Num!(0)]);
+}
+
 fn lt_block_num() {
     // TODO: Block internals should be treated as a string
     // Example:
@@ -478,20 +641,20 @@ fn lt_block_num() {
 // test>
 #[test]
 fn gt_num() {
-    assert_eq!(eval("3 4>"), [Num(0)]);
-    assert_eq!(eval("5 4>"), [Num(1)]);
+    assert_eq!(eval("3 4>"), [Num!(0)]);
+    assert_eq!(eval("5 4>"), [Num!(1)]);
 }
 
 #[test]
 fn gt_str() {
-    assert_eq!(eval("\"asdf\"\"asdg\">"), [Num(0)]);
-    assert_eq!(eval("\"zzdf\"\"asdg\">"), [Num(1)]);
+    assert_eq!(eval("\"asdf\"\"asdg\">"), [Num!(0)]);
+    assert_eq!(eval("\"zzdf\"\"asdg\">"), [Num!(1)]);
 }
 
 #[test]
 fn gt_array_num() {
-    assert_eq!(eval("[1 2 3]2>"), [Array!([Num(3)])]);
-    assert_eq!(eval("[1 2 3]-2>"), [Array!([Num(2), Num(3)])]);
+    assert_eq!(eval("[1 2 3]2>"), [Array!([Num!(3)])]);
+    assert_eq!(eval("[1 2 3]-2>"), [Array!([Num!(2), Num!(3)])]);
 }
 
 #[test]
@@ -510,38 +673,44 @@ fn gt_block_num() {
 // test=
 #[test]
 fn eq_num() {
-    assert_eq!(eval("3 4="), [Num(0)]);
-    assert_eq!(eval("4 4="), [Num(1)]);
+    assert_eq!(eval("3 4="), [Num!(0)]);
+    assert_eq!(eval("4 4="), [Num!(1)]);
 }
 
 #[test]
 fn eq_str() {
-    assert_eq!(eval("\"asdf\"\"asdg\"="), [Num(0)]);
-    assert_eq!(eval("\"asdg\"\"asdg\"="), [Num(1)]);
+    assert_eq!(eval("\"asdf\"\"asdg\"="), [Num!(0)]);
+    assert_eq!(eval("\"asdg\"\"asdg\"="), [Num!(1)]);
 }
 
 #[test]
 fn eq_array() {
-    assert_eq!(eval("[1 2 3] [1 1 3]="), [Num(0)]);
-    assert_eq!(eval("[1 2 3] [1 2 3]="), [Num(1)]);
+    assert_eq!(eval("[1 2 3] [1 1 3]="), [Num!(0)]);
+    assert_eq!(eval("[1 2 3] [1 2 3]="), [Num!(1)]);
 }
 
 #[test]
 fn eq_block() {
-    assert_eq!(eval("{1 2 3} {1 1 3}="), [Num(0)]);
-    assert_eq!(eval("{1 2 3} {1 2 3}="), [Num(1)]);
+    assert_eq!(eval("{1 2 3} {1 1 3}="), [Num!(0)]);
+    assert_eq!(eval("{1 2 3} {1 2 3}="), [Num!(1)]);
 }
 
 #[test]
 fn eq_num_array() {
-    assert_eq!(eval("[1 2 3]2="), [Num(3)]);
-    assert_eq!(eval("[1 2 3]-1="), [Num(3)]);
+    assert_eq!(eval("[1 2 3]2="), [Num!(3)]);
+    assert_eq!(eval("[1 2 3]-1="), [Num!(3)]);
 }
 
 #[test]
 fn eq_num_str() {
-    assert_eq!(eval("\"asdf\" -1 ="), [Num(102)]);
-    assert_eq!(eval("\"asdf\" 2 ="), [Num(100)]);
+    assert_eq!(eval("\"asdf\" -1 ="), [Num!(102)]);
+    assert_eq!(eval("\"asdf\" 2 ="), [Num!(100)]);
+}
+
+#[test]
+fn eq_coercion() {
+    assert_eq!(eval("\"a\"[97]="), [Num!(1)]);
+    assert_eq!(eval("\"a\"[98]="), [Num!(0)]);
 }
 
 fn eq_block_num() {
@@ -553,88 +722,154 @@ fn eq_block_num() {
 //test,
 #[test]
 fn comma_num() {
-    assert_eq!(eval("3,"), [Array!([Num(0), Num(1), Num(2)])]);
+    assert_eq!(eval("3,"), [Array!([Num!(0), Num!(1), Num!(2)])]);
 }
 
 #[test]
 fn comma_array() {
-    assert_eq!(eval("[1,1,1],"), [Num(3)]);
-    assert_eq!(eval("10,,"), [Num(10)]);
+    assert_eq!(eval("[1,1,1],"), [Num!(3)]);
+    assert_eq!(eval("10,,"), [Num!(10)]);
 }
 
 #[test]
 fn comma_block() {
-    assert_eq!(eval("5,{3%},"), [Array!([Num(1), Num(2), Num(4)])]);
+    assert_eq!(eval("5,{3%},"), [Array!([Num!(1), Num!(2), Num!(4)])]);
+}
+
+#[test]
+fn comma_str() {
+    assert_eq!(eval("\"abc\","), [Num!(3)]);
+    assert_eq!(eval("\"\","), [Num!(0)]);
+}
+
+#[test]
+fn comma_block_str() {
+    assert_eq!(eval("\"hello\"{108<},"), [Str!("he")]);
+    assert_eq!(eval("\"abc\"{;0},"), [Str!("")]);
 }
 
 // test.
 #[test]
 fn dot() {
-    assert_eq!(eval("1."), [Num(1), Num(1)]);
-    assert_eq!(eval("[1]."), [Array!([Num(1)]), Array!([Num(1)])]);
+    assert_eq!(eval("1."), [Num!(1), Num!(1)]);
+    assert_eq!(eval("[1]."), [Array!([Num!(1)]), Array!([Num!(1)])]);
     assert_eq!(eval("\"asdf\"."), [Str!("asdf"), Str!("asdf")]);
-    assert_eq!(eval("{1}."), [Block!([Num(1)]), Block!([Num(1)])]);
+    assert_eq!(eval("{1}."), [Block!([Num!(1)]), Block!([Num!(1)])]);
+}
+
+#[test]
+fn dot_copy_on_write() {
+    assert_eq!(
+        eval("[3 1 2].$"),
+        [
+            Array!([Num!(3), Num!(1), Num!(2)]),
+            Array!([Num!(1), Num!(2), Num!(3)])
+        ]
+    );
+    assert_eq!(eval("[1 2]:a;a(;;a"), [Array!([Num!(1), Num!(2)])]);
+    assert_eq!(
+        eval("[1 2]:a;[a)]a"),
+        [
+            Array!([Array!([Num!(1)]), Num!(2)]),
+            Array!([Num!(1), Num!(2)])
+        ]
+    );
 }
 
 // test?
 #[test]
 fn qmark_num() {
-    assert_eq!(eval("2 8?"), [Num(256)]);
+    assert_eq!(eval("2 8?"), [Num!(256)]);
+    assert_eq!(
+        eval("2 100?"),
+        [Num!(BigInt::parse_bytes(
+            b"1267650600228229401496703205376",
+            10
+        )
+        .unwrap())]
+    );
 }
 
 #[test]
 fn qmark_num_array() {
-    assert_eq!(eval("5 [4 3 5 1]?"), [Num(2)]);
-    assert_eq!(eval("10 [4 3 5 1]?"), [Num(-1)]);
+    assert_eq!(eval("5 [4 3 5 1]?"), [Num!(2)]);
+    assert_eq!(eval("10 [4 3 5 1]?"), [Num!(-1)]);
 }
 
 #[test]
 fn qmark_block_array() {
-    assert_eq!(eval("[1 2 3 4 5 6] {.* 20>} ?"), [Num(5)]);
+    assert_eq!(eval("[1 2 3 4 5 6] {.* 20>} ?"), [Num!(5)]);
     assert_eq!(eval("[1 2 3 4 5 6] {.* -1=} ?"), []);
 }
 
+#[test]
+fn qmark_block_str() {
+    assert_eq!(eval("\"hello\"{104>}?"), [Num!(108)]);
+    assert_eq!(eval("{104>}\"hello\"?"), [Num!(108)]);
+    assert_eq!(eval("\"abc\"{0}?"), []);
+    assert_eq!(eval("{2>}[1 2 3 4]?"), [Num!(3)]);
+}
+
+#[test]
+fn qmark_index() {
+    assert_eq!(eval("[4 3 5 1] 5?"), [Num!(2)]);
+    assert_eq!(eval("[\"a\" \"b\"] \"b\"?"), [Num!(1)]);
+    assert_eq!(eval("\"hello\" \"ll\"?"), [Num!(2)]);
+    assert_eq!(eval("\"hello\" \"x\"?"), [Num!(-1)]);
+    assert_eq!(eval("\"hello\" 108?"), [Num!(2)]);
+}
+
 // test(
 #[test]
 fn dec_num() {
-    assert_eq!(eval("5("), [Num(4)]);
+    assert_eq!(eval("5("), [Num!(4)]);
 }
 
 #[test]
 fn dec_array() {
-    assert_eq!(eval("[1 2 3]("), [Array!([Num(2), Num(3)]), Num(1)]);
+    assert_eq!(eval("[1 2 3]("), [Array!([Num!(2), Num!(3)]), Num!(1)]);
+}
+
+#[test]
+fn dec_str() {
+    assert_eq!(eval("\"abc\"("), [Str!("bc"), Num!(97)]);
+    assert_eq!(eval("\"\"("), []);
 }
 
 // test)
 #[test]
 fn inc_num() {
-    assert_eq!(eval("5)"), [Num(6)]);
+    assert_eq!(eval("5)"), [Num!(6)]);
 }
 
 #[test]
 fn inc_array() {
-    assert_eq!(eval("[1 2 3])"), [Array!([Num(1), Num(2)]), Num(3)]);
+    assert_eq!(eval("[1 2 3])"), [Array!([Num!(1), Num!(2)]), Num!(3)]);
+}
+
+#[test]
+fn inc_str() {
+    assert_eq!(eval("\"abc\")"), [Str!("ab"), Num!(99)]);
+    assert_eq!(eval("\"\")"), []);
 }
 
 //test lazy_bool
 #[test]
 fn lazy_bool_and() {
-    assert_eq!(eval("5 {1 1+} and"), [Num(2)]);
+    assert_eq!(eval("5 {1 1+} and"), [Num!(2)]);
 }
 
 #[test]
 fn lazy_bool_or() {
-    assert_eq!(eval("5 {1 0/} or"), [Num(5)]);
+    assert_eq!(eval("5 {1 0/} or"), [Num!(5)]);
 }
 
 #[test]
 fn lazy_bool_xor() {
-    assert_eq!(eval("0 [3] xor"), [Array!([Num(3)])]);
-    assert_eq!(eval("2 [3] xor"), [Num(0)]);
+    assert_eq!(eval("0 [3] xor"), [Array!([Num!(3)])]);
+    assert_eq!(eval("2 [3] xor"), [Num!(0)]);
 }
 
-// TODO: find a way to test builtin `print`, `p`, and `puts`
-
 // test n
 #[test]
 fn builtin_n() {
@@ -644,19 +879,70 @@ fn builtin_n() {
 // test if
 #[test]
 fn builtin_if() {
-    assert_eq!(eval("1 2 3if"), [Num(2)]);
-    assert_eq!(eval("0 2 3if"), [Num(3)]);
+    assert_eq!(eval("1 2 3if"), [Num!(2)]);
+    assert_eq!(eval("0 2 3if"), [Num!(3)]);
 }
 
 #[test]
 fn builtin_if_block() {
-    assert_eq!(eval("0 2 {1.} if"), [Num(1), Num(1)]);
+    assert_eq!(eval("0 2 {1.} if"), [Num!(1), Num!(1)]);
+}
+
+// test do
+#[test]
+fn builtin_do() {
+    assert_eq!(
+        eval("5{.1-.}do"),
+        [Num!(5), Num!(4), Num!(3), Num!(2), Num!(1), Num!(0)]
+    );
+    assert_eq!(eval("0{1+.5<}do"), [Num!(5)]);
+    assert_eq!(eval("0{.}do"), [Num!(0)]);
+}
+
+// test while
+#[test]
+fn builtin_while() {
+    assert_eq!(
+        eval("5{.}{1-.}while"),
+        [Num!(4), Num!(3), Num!(2), Num!(1), Num!(0), Num!(0)]
+    );
+    assert_eq!(eval("0{.}{1-.}while"), [Num!(0)]);
+}
+
+// test until
+#[test]
+fn builtin_until() {
+    assert_eq!(eval("5{.}{1-.}until"), [Num!(5)]);
+    assert_eq!(eval("0{.}{1+}until"), [Num!(1)]);
 }
 
 // test abs
 #[test]
 fn builtin_abs() {
-    assert_eq!(eval("-2abs"), [Num(2)]);
+    assert_eq!(eval("-2abs"), [Num!(2)]);
+    assert_eq!(
+        eval("-123456789012345678901234567890abs"),
+        [Num!(BigInt::parse_bytes(
+            b"123456789012345678901234567890",
+            10
+        )
+        .unwrap())]
+    );
+}
+
+// test rand
+#[test]
+fn rand_seeded() {
+    let numbers = |seed| {
+        let mut it = Interpreter::with_seed(seed);
+        it.exec("20,{;1000rand}%").unwrap().to_vec()
+    };
+    assert_eq!(numbers(42), numbers(42));
+    assert_ne!(numbers(42), numbers(43));
+
+    let mut it = Interpreter::default();
+    it.set_rng(StepRng::new(0, 0));
+    assert_eq!(it.exec("10rand -10rand").unwrap(), [Num!(0), Num!(-10)]);
 }
 
 // test zip
@@ -665,9 +951,9 @@ fn builtin_zip_array() {
     assert_eq!(
         eval("[[1 2 3][4 5 6][7 8 9]]zip"),
         [Array!([
-            Array!([Num(1), Num(4), Num(7)]),
-            Array!([Num(2), Num(5), Num(8)]),
-            Array!([Num(3), Num(6), Num(9)])
+            Array!([Num!(1), Num!(4), Num!(7)]),
+            Array!([Num!(2), Num!(5), Num!(8)]),
+            Array!([Num!(3), Num!(6), Num!(9)])
         ])]
     );
 }
@@ -682,7 +968,7 @@ fn builtin_zip_string() {
 
 #[test]
 fn builtin_base_from() {
-    assert_eq!(eval("[\"1\" \"1\" \"0\"] 2 base"), [Num(6)]);
+    assert_eq!(eval("[\"1\" \"1\" \"0\"] 2 base"), [Num!(6)]);
 }
 
 #[test]
@@ -692,3 +978,784 @@ fn builtin_base_to() {
         [Array!([Str!("1"), Str!("1"), Str!("0")])]
     );
 }
+
+// test io
+#[test]
+fn io_print_to_writer() {
+    assert_eq!(exec_with_stdin("\"ab\"print", ""), "ab");
+}
+
+// test print puts p
+fn printed(input: &str) -> String {
+    exec_with_stdin(input, "")
+}
+
+#[test]
+fn print_golden() {
+    assert_eq!(printed("\"a\nb\"print"), "a\nb");
+    assert_eq!(printed("1 print 2 print"), "12");
+    assert_eq!(printed("[1 [2 [\"x\"]] \"yz\"]print"), "12xyz");
+    assert_eq!(printed("{1 \"a\" +}print"), "{1 \"a\" +}");
+    assert_eq!(printed("[]print"), "");
+}
+
+#[test]
+fn puts_golden() {
+    assert_eq!(printed("\"ab\"puts"), "ab\n");
+    assert_eq!(printed("1 puts 2 puts"), "1\n2\n");
+    assert_eq!(printed("[1 [2 [\"x\"]] \"yz\"]puts"), "12xyz\n");
+    assert_eq!(printed("{1 \"a\" +}puts"), "{1 \"a\" +}\n");
+}
+
+#[test]
+fn p_golden() {
+    assert_eq!(printed("\"ab\"p"), "\"ab\"\n");
+    assert_eq!(printed("1 p"), "1\n");
+    assert_eq!(
+        printed("[1 [2 [\"x\"]] \"yz\"]p"),
+        "[1 [2 [\"x\"]] \"yz\"]\n"
+    );
+    assert_eq!(printed("{1 \"a\" +}p"), "{1 \"a\" +}\n");
+    assert_eq!(printed("{1 2+}p"), "{1 2+}\n");
+    assert_eq!(printed("{'a'{}}p"), "{'a'{}}\n");
+}
+
+#[test]
+fn print_leaves_stack() {
+    assert_eq!(eval("1 2 \"\"print"), [Num!(1), Num!(2)]);
+}
+
+#[test]
+fn io_run_reads_input() {
+    assert_eq!(run_with_stdin("", "abc"), "abc\n");
+    assert_eq!(run_with_stdin(".+", "ab"), "abab\n");
+    assert_eq!(run_with_stdin(";[1 [2] \"x\"]", "ignored"), "12x\n");
+}
+
+// test bytecode engine
+fn eval_engine(input: &str, engine: Engine) -> Result<Vec<Item>, GSError> {
+    let mut it = Interpreter::new();
+    it.set_engine(engine);
+    it.exec(input).map(|x| x.to_vec())
+}
+
+#[test]
+fn bytecode_matches_tree() {
+    let programs = [
+        "[1 2 3]{.*}%",
+        "\"abc\"{)}%",
+        "{1+}[1 2]%",
+        "5{)}%",
+        "[1 2 3]{1$+}%",
+        "0[1 2 3]{+}/",
+        "\"ab\"{}/",
+        "[1 2 3 4]{+}*",
+        "\"abc\"{+}*",
+        "3{1}*",
+        "0{1+.5<}do",
+        "{.*}:sq;[1 2 3]{sq}%",
+        "{-}:+;[5 3]{+}*",
+        "{;1}:%;[1 2]{2}%",
+        "[1 [2 3]{+}*]",
+        "1 2 +\n  foo",
+        "[1 2]{0/}%",
+        "\"ab\"{-1}%",
+        "1[(]",
+        "1[2+]",
+        "[1 2+]",
+        "[1 2 3]{[\\1+]}%",
+        "3 4<5 5=6 2>",
+        "1[.]",
+        "[1 2]{[.]}%",
+    ];
+    for program in programs.iter() {
+        assert_eq!(
+            eval_engine(program, Engine::Bytecode),
+            eval_engine(program, Engine::Tree),
+            "{}",
+            program
+        );
+    }
+}
+
+#[test]
+fn bytecode_exec_items() {
+    let mut it = Interpreter::new();
+    it.set_engine(Engine::Bytecode);
+    assert_eq!(it.engine(), Engine::Bytecode);
+    let items = [
+        Num!(3),
+        Var(",".to_string()),
+        Block!([Num!(2), Var("*".to_string())]),
+        Var("%".to_string()),
+    ];
+    assert_eq!(
+        it.exec_items(&items).unwrap(),
+        [Array!([Num!(0), Num!(2), Num!(4)])]
+    );
+}
+
+// test limits
+fn eval_limited(input: &str, limits: Limits) -> Vec<Result<Vec<Item>, ErrorKind>> {
+    [Engine::Tree, Engine::Bytecode]
+        .iter()
+        .map(|&engine| {
+            let mut it = Interpreter::new();
+            it.set_engine(engine);
+            it.set_limits(limits);
+            it.exec(input).map(|x| x.to_vec()).map_err(|e| e.kind)
+        })
+        .collect()
+}
+
+fn limit_exceeded(limit: Limit) -> Vec<Result<Vec<Item>, ErrorKind>> {
+    vec![Err(ErrorKind::LimitExceeded(limit)); 2]
+}
+
+#[test]
+fn limit_ops() {
+    let limits = Limits {
+        max_ops: Some(1000),
+        ..Limits::default()
+    };
+    assert_eq!(eval_limited("{1}do", limits), limit_exceeded(Limit::Ops));
+    assert!(eval_limited("[10,{.*}%]", limits)[0].is_ok());
+
+    // the count restarts on each call
+    let mut it = Interpreter::new();
+    it.set_limits(Limits {
+        max_ops: Some(3),
+        ..Limits::default()
+    });
+    assert!(it.exec("1 1+").is_ok());
+    assert!(it.exec("1+").is_ok());
+    assert_eq!(
+        it.exec("1+1+").unwrap_err().kind,
+        ErrorKind::LimitExceeded(Limit::Ops)
+    );
+}
+
+#[test]
+fn limit_time() {
+    let limits = Limits {
+        max_time: Some(Duration::from_millis(20)),
+        ..Limits::default()
+    };
+    assert_eq!(eval_limited("{1}do", limits), limit_exceeded(Limit::Time));
+}
+
+#[test]
+fn limit_depth() {
+    let limits = Limits {
+        max_depth: Some(50),
+        ..Limits::default()
+    };
+    assert_eq!(
+        eval_limited("{f}:f;f", limits),
+        limit_exceeded(Limit::Depth)
+    );
+    assert_eq!(
+        eval_limited("\".~\".~", limits),
+        limit_exceeded(Limit::Depth)
+    );
+    assert_eq!(eval_limited("{.~}.~", limits), limit_exceeded(Limit::Depth));
+    assert_eq!(
+        eval_limited("{.1\\{}if}.~", limits),
+        limit_exceeded(Limit::Depth)
+    );
+    assert_eq!(
+        eval_limited("{.}:f;{\"f\"~}:g;1 g", limits),
+        vec![Ok(vec![Num!(1), Num!(1)]); 2]
+    );
+}
+
+#[test]
+fn limit_stack() {
+    let limits = Limits {
+        max_stack: Some(100),
+        ..Limits::default()
+    };
+    assert_eq!(
+        eval_limited("0{1.}do", limits),
+        limit_exceeded(Limit::Stack)
+    );
+    assert_eq!(eval_limited("200,~", limits), limit_exceeded(Limit::Stack));
+}
+
+#[test]
+fn limit_item_size() {
+    let limits = Limits {
+        max_item_size: Some(100),
+        ..Limits::default()
+    };
+    assert_eq!(
+        eval_limited("1000000000000,", limits),
+        limit_exceeded(Limit::ItemSize)
+    );
+    assert_eq!(
+        eval_limited("\"ab\" 1000000000000*", limits),
+        limit_exceeded(Limit::ItemSize)
+    );
+    assert_eq!(
+        eval_limited("[1] 1000000000000*", limits),
+        limit_exceeded(Limit::ItemSize)
+    );
+    assert_eq!(
+        eval_limited("2 1000000000000?", limits),
+        limit_exceeded(Limit::ItemSize)
+    );
+    assert_eq!(
+        eval_limited("\"a\"{.+.}do", limits),
+        limit_exceeded(Limit::ItemSize)
+    );
+    assert_eq!(
+        eval_limited("1 1000000000000?", limits)[0],
+        Ok(vec![Num!(1)])
+    );
+}
+
+#[test]
+fn limit_allocation() {
+    let limits = Limits {
+        max_allocation: Some(10_000),
+        ..Limits::default()
+    };
+    for input in &[
+        "\"a\" 1000000*",
+        "[0] 1000000*",
+        "1000000,",
+        "\"ab\" 5000* .+",
+        "[0] 300* .+",
+        "[0] 300* [[1]] *",
+        "\"abc\" 5000* \",\"*",
+        "[\"a\" 3000*] 5000* \"\"*",
+        "[[0] 200*] 5 * zip",
+        "2 100000? 2 base",
+        "{.+.}\"a\"\\do",
+        // every builtin building an array or string is charged
+        "[1] 20{{.}%}*,",
+        "\"ab\" 22{{.}%}*,",
+        "[0] 100* 100{.|}*",
+        "\"ab\" 3000* 10{.$}*",
+        "[0] 200* 20{.1>}*",
+        "\"a b\" 1000* 20{.\" \"/;}*",
+    ] {
+        assert_eq!(
+            eval_limited(input, limits),
+            limit_exceeded(Limit::Allocation),
+            "{}",
+            input
+        );
+    }
+
+    // the budget is reset on every call
+    let mut interpreter = Interpreter::default();
+    interpreter.set_limits(limits);
+    for _ in 0..10 {
+        interpreter.exec("\"a\" 9000*;").unwrap();
+    }
+    assert_eq!(
+        eval_limited("\"a\" 9000*,", limits)[0],
+        Ok(vec![Num!(9000)])
+    );
+    // the limit bounds the volume allocated, even if little is kept
+    let limits = Limits {
+        max_allocation: Some(1 << 20),
+        ..Limits::default()
+    };
+    assert_eq!(
+        eval_limited("\"\" 3000{\"a\"+}*,", limits),
+        limit_exceeded(Limit::Allocation)
+    );
+    assert_eq!(
+        eval_limited("\"\" 1000{\"a\"+}*,", limits),
+        vec![Ok(vec![Num!(1000)]); 2]
+    );
+}
+
+// test type errors
+fn type_mismatch(op: &str, types: &[&'static str], depth: usize) -> ErrorKind {
+    ErrorKind::TypeMismatch {
+        op: op.to_string(),
+        types: types.to_vec(),
+        depth,
+    }
+}
+
+#[test]
+fn too_large_to_allocate() {
+    let out_of_range = |len: &str| ErrorKind::NumberOutOfRange(len.parse().unwrap());
+    assert_eq!(
+        eval_err("\"ab\" 9223372036854775807*"),
+        out_of_range("18446744073709551614")
+    );
+    assert_eq!(
+        eval_err("[1] 4611686018427387904*"),
+        out_of_range("4611686018427387904")
+    );
+    assert_eq!(
+        eval_err("9223372036854775807,"),
+        out_of_range("9223372036854775807")
+    );
+}
+
+#[test]
+fn type_error_unary() {
+    assert_eq!(eval_err("\"a\"rand"), type_mismatch("rand", &["string"], 1));
+    assert_eq!(
+        eval_err("1 [1 \"a\"]zip"),
+        type_mismatch("zip", &["array"], 2)
+    );
+    assert_eq!(eval_err("{1}("), type_mismatch("(", &["block"], 1));
+}
+
+#[test]
+fn type_error_binary() {
+    assert_eq!(
+        eval_err("1 {1}{2}*"),
+        type_mismatch("*", &["block", "block"], 3)
+    );
+    assert_eq!(
+        eval_err("{1} 2 base"),
+        type_mismatch("base", &["block", "number"], 2)
+    );
+    assert_eq!(
+        eval_err("{1} 1<"),
+        type_mismatch("<", &["block", "number"], 2)
+    );
+    assert_eq!(
+        eval_err("1 2 while"),
+        type_mismatch("while", &["number", "number"], 2)
+    );
+}
+
+// test error kinds and positions
+#[test]
+fn error_kinds() {
+    assert_eq!(eval_err("+"), ErrorKind::StackUnderflow);
+    assert_eq!(eval_err("1 0/"), ErrorKind::DivisionByZero);
+    assert_eq!(
+        eval_err("foo"),
+        ErrorKind::UndefinedVariable("foo".to_string())
+    );
+    assert_eq!(eval_err("1 \"abc"), ErrorKind::UnterminatedString);
+    assert_eq!(eval_err("{1 2"), ErrorKind::UnterminatedBlock);
+    assert_eq!(eval_err("1 }"), ErrorKind::UnexpectedChar('}'));
+    assert_eq!(eval_err("1 2:"), ErrorKind::EmptyAssignment);
+}
+
+#[test]
+fn error_span() {
+    let err = eval_("1 2 +\n  foo").unwrap_err();
+    assert_eq!(
+        err.span,
+        Some(Span {
+            start: 8,
+            end: 11,
+            line: 2,
+            column: 3
+        })
+    );
+    assert_eq!(err.to_string(), "undefined variable `foo` at 2:3");
+
+    // errors inside a block point to the token that executed it
+    let err = eval_("{0/}:f;\n1 f").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::DivisionByZero);
+    assert_eq!(err.span.map(|s| (s.line, s.column)), Some((2, 3)));
+
+    let err = eval_("1 {\"a}").unwrap_err();
+    assert_eq!(err.kind, ErrorKind::UnterminatedString);
+    assert_eq!(err.span.map(|s| s.column), Some(4));
+}
+
+// test transactional execution
+#[test]
+fn exec_rolls_back() {
+    for &engine in &[Engine::Tree, Engine::Bytecode] {
+        let mut it = Interpreter::default();
+        it.set_engine(engine);
+        assert!(it.transactional());
+        it.exec("1 2").unwrap();
+        let err = it.exec("[3 4:x;{-}:+;{;}:f f 0/").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::DivisionByZero);
+        assert_eq!(it.exec("").unwrap(), [Num!(1), Num!(2)]);
+        assert_eq!(
+            it.exec("x").unwrap_err().kind,
+            ErrorKind::UndefinedVariable("x".to_string())
+        );
+        assert_eq!(
+            it.exec("f").unwrap_err().kind,
+            ErrorKind::UndefinedVariable("f".to_string())
+        );
+        assert_eq!(it.exec("]").unwrap_err().kind, ErrorKind::MarkerUnderflow);
+        assert_eq!(it.exec("+").unwrap(), [Num!(3)]);
+
+        let mut it = Interpreter::default();
+        it.set_engine(engine);
+        it.exec("1 2").unwrap();
+        assert!(it
+            .exec_items(&[Num!(3), Assign("x".to_string()), Var("f".to_string())])
+            .is_err());
+        assert_eq!(it.exec("").unwrap(), [Num!(1), Num!(2)]);
+    }
+}
+
+#[test]
+fn exec_streaming() {
+    let mut it = Interpreter::default();
+    it.set_transactional(false);
+    it.exec("1 2").unwrap();
+    assert!(it.exec("3:x;f").is_err());
+    assert_eq!(it.exec("x").unwrap(), [Num!(1), Num!(2), Num!(3)]);
+}
+
+// test native builtins
+fn native_interpreter(engine: Engine) -> Interpreter {
+    let mut it = Interpreter::default();
+    it.set_engine(engine);
+    it.register_builtin("sq", |stack: &mut Stack| match stack.pop()? {
+        Num(x) => {
+            stack.push(Num(&x * &x));
+            Ok(())
+        }
+        x => Err(ErrorKind::TypeMismatch {
+            op: "sq".to_string(),
+            types: vec![x.type_name()],
+            depth: stack.len() + 1,
+        }
+        .into()),
+    });
+    it.register_builtin("abs", |stack: &mut Stack| {
+        stack.push(Str!("abs"));
+        Ok(())
+    });
+    it
+}
+
+#[test]
+fn native_builtin() {
+    for &engine in &[Engine::Tree, Engine::Bytecode] {
+        let mut it = native_interpreter(engine);
+        assert_eq!(
+            it.exec("3 sq [1 2 3]{sq}% -1 abs").unwrap(),
+            [
+                Num!(9),
+                Array!([Num!(1), Num!(4), Num!(9)]),
+                Num!(-1),
+                Str!("abs")
+            ]
+        );
+
+        let mut it = native_interpreter(engine);
+        assert_eq!(
+            it.exec("\"a\" sq").unwrap_err().kind,
+            type_mismatch("sq", &["string"], 1)
+        );
+
+        // user assignment overrides native builtins
+        let mut it = native_interpreter(engine);
+        assert_eq!(
+            it.exec("{.+}:sq; 3 sq 5:abs; abs").unwrap(),
+            [Num!(6), Num!(5)]
+        );
+    }
+}
+
+// test stack and variable API
+#[test]
+fn host_api() {
+    let mut it = Interpreter::default();
+    it.push(Num!(2));
+    it.set_variable("x", Num!(5));
+    it.set_variable("double", Block!([Num!(2), Var("*".to_string())]));
+    it.exec("x double +").unwrap();
+    assert_eq!(it.peek(), Ok(&Num!(12)));
+    assert_eq!(it.stack().len(), 1);
+    assert_eq!(it.pop(), Ok(Num!(12)));
+    assert_eq!(it.pop().unwrap_err().kind, ErrorKind::StackUnderflow);
+
+    it.stack_mut().push(Num!(1));
+    it.exec("[2").unwrap();
+    it.clear_stack();
+    assert_eq!(it.exec("]").unwrap_err().kind, ErrorKind::MarkerUnderflow);
+    assert!(it.stack().is_empty());
+
+    it.exec("1:n;{;}:+;").unwrap();
+    assert_eq!(it.variable("x"), Some(&Num!(5)));
+    assert_eq!(it.variable("n"), Some(&Num!(1)));
+    it.register_builtin("puts", |stack: &mut Stack| stack.pop().map(|_| ()));
+    it.reset();
+    assert_eq!(it.variable("x"), None);
+    assert_eq!(it.variable("n"), Some(&Str!("\n")));
+    assert_eq!(it.exec("1 2+ puts").unwrap(), []);
+}
+
+// test conversions
+#[test]
+fn item_conversions() {
+    assert_eq!(Item::from(3i64), Num!(3));
+    assert_eq!(Item::from(true), Num!(1));
+    assert_eq!(Item::from('a'), Num!(97));
+    assert_eq!(Item::from("ab"), Str!("ab"));
+    assert_eq!(Item::from("ab".to_string()), Str!("ab"));
+    assert_eq!(
+        Item::from(vec![vec![1i64], vec![]]),
+        Array!([Array!([Num!(1)]), Array!([])])
+    );
+    assert_eq!(vec!["a", "b"].into_item(), Array!([Str!("a"), Str!("b")]));
+
+    assert_eq!(i64::try_from(Num!(-3)), Ok(-3));
+    assert_eq!(char::try_from(Num!(97)), Ok('a'));
+    assert_eq!(bool::try_from(Str!("")), Ok(false));
+    assert_eq!(String::try_from(Str!("ab")), Ok("ab".to_string()));
+    assert_eq!(
+        Vec::<i64>::try_from(Array!([Num!(1), Num!(2)])),
+        Ok(vec![1, 2])
+    );
+    assert_eq!(
+        Vec::<String>::from_item(Array!([Str!("a")])),
+        Ok(vec!["a".to_string()])
+    );
+
+    let big = BigInt::from(i64::MAX) + BigInt::from(1);
+    assert_eq!(
+        i64::try_from(Num(big.clone())).unwrap_err().kind,
+        ErrorKind::NumberOutOfRange(big)
+    );
+    assert_eq!(
+        Vec::<i64>::try_from(Array!([Num!(1), Str!("2")]))
+            .unwrap_err()
+            .kind,
+        ErrorKind::UnexpectedType {
+            expected: "number",
+            found: "string"
+        }
+    );
+    assert_eq!(
+        String::from_item(Num!(1)).unwrap_err().to_string(),
+        "expected string, found number"
+    );
+
+    let mut it = Interpreter::default();
+    it.register_builtin("sum", |stack: &mut Stack| {
+        let xs: Vec<i64> = stack.pop_as()?;
+        stack.push(xs.iter().sum::<i64>().into());
+        Ok(())
+    });
+    assert_eq!(it.exec("[1 2 3]sum").unwrap(), [Num!(6)]);
+}
+
+// test call
+#[test]
+fn call_function() {
+    for &engine in &[Engine::Tree, Engine::Bytecode] {
+        let mut it = Interpreter::default();
+        it.set_engine(engine);
+        it.exec("1 {.*}:sq; {[\\.]}:pair;").unwrap();
+        assert_eq!(it.call("sq", &[Num!(7)]), Ok(vec![Num!(49)]));
+        assert_eq!(
+            it.call("pair", &["a".into_item(), Num!(2)]),
+            Ok(vec![Array!([Num!(2), Str!("a"), Str!("a")])])
+        );
+        assert_eq!(it.call("+", &[Num!(1), Num!(2)]), Ok(vec![Num!(3)]));
+        assert_eq!(it.call("n", &[]), Ok(vec![Str!("\n")]));
+
+        // the stack of the interpreter is out of reach
+        assert_eq!(
+            it.call("sq", &[]).unwrap_err().kind,
+            ErrorKind::StackUnderflow
+        );
+        assert_eq!(
+            it.call("f", &[]).unwrap_err().kind,
+            ErrorKind::UndefinedVariable("f".to_string())
+        );
+        assert_eq!(it.stack().len(), 1);
+        assert_eq!(it.peek(), Ok(&Num!(1)));
+    }
+}
+
+// test tokenizer conformance with the reference regex
+#[test]
+fn tokenize_reference() {
+    let table: &[(&str, &[&str])] = &[
+        ("", &[]),
+        ("abc_1 _x A", &["abc_1", " ", "_x", " ", "A"]),
+        ("1a", &["1", "a"]),
+        ("a1-1", &["a1", "-1"]),
+        ("1-1", &["1", "-1"]),
+        ("1 -1", &["1", " ", "-1"]),
+        ("--1", &["-", "-1"]),
+        ("- 1", &["-", " ", "1"]),
+        ("1.5", &["1", ".", "5"]),
+        ("007", &["007"]),
+        ("é1", &["é", "1"]),
+        ("\"a\\\"b\"c", &["\"a\\\"b\"", "c"]),
+        ("'a\\'b'c", &["'a\\'b'", "c"]),
+        ("'a\"b'", &["'a\"b'"]),
+        ("\"a\nb\"", &["\"a\nb\""]),
+        ("\"ab", &["\"ab"]),
+        ("'ab\\", &["'ab\\"]),
+        ("#c\r1", &["#c", "\r", "1"]),
+        ("1#c\n2", &["1", "#c", "\n", "2"]),
+        ("{1}:x", &["{", "1", "}", ":", "x"]),
+        ("\t\n", &["\t", "\n"]),
+        ("$@`", &["$", "@", "`"]),
+    ];
+    for &(input, tokens) in table {
+        assert_eq!(golfscript::tokenize(input), tokens, "{:?}", input);
+    }
+}
+
+#[test]
+fn lex_reference() {
+    assert_eq!(eval("1:a;a-1"), [Num!(1), Num!(-1)]);
+    assert_eq!(eval("'a\\'b\\\\c\\n'"), [Str!("a'b\\c\\n")]);
+    assert_eq!(eval("'\"'\"'\""), [Str!("\""), Str!("'")]);
+    assert_eq!(eval("5:_a1;_a1 1#comment\r2"), [Num!(5), Num!(1), Num!(2)]);
+    assert_eq!(eval_err("é"), ErrorKind::UndefinedVariable("é".to_string()));
+    assert_eq!(eval_err("'abc"), ErrorKind::UnterminatedString);
+    assert_eq!(eval_err("1:"), ErrorKind::EmptyAssignment);
+}
+
+// test string escapes
+#[test]
+fn string_escapes() {
+    let table: &[(&str, &str)] = &[
+        (r#""a\nb\tc\rd""#, "a\nb\tc\rd"),
+        (r#""\s\e\a\b\f\v""#, " \x1b\x07\x08\x0c\x0b"),
+        (r#""\x41\x7a\x9""#, "Az\t"),
+        (r#""\101\0\12\777""#, "A\0\n\u{ff}"),
+        (r#""é\u{41 1F600}""#, "éA\u{1F600}"),
+        (r#""\ca\C-z\c?\M-a""#, "\x01\x1a\x7f\u{e1}"),
+        (r#""\q\"\\\#{""#, "q\"\\#{"),
+        ("\"a\\\nb\"", "ab"),
+        ("\"é\"", "é"),
+        (r#"'a\nb\'\\'"#, "a\\nb'\\"),
+    ];
+    for &(input, string) in table {
+        assert_eq!(eval(input), [Str!(string)], "{}", input);
+    }
+
+    for input in &[r#""\x""#, r#""\u12""#, r#""\u{d800}""#, r#""\M-é""#] {
+        match eval_err(input) {
+            ErrorKind::InvalidEscape(_) => (),
+            err => panic!("{}: {:?}", input, err),
+        }
+    }
+    assert_eq!(eval_err("\"\\"), ErrorKind::UnterminatedString);
+}
+
+#[test]
+fn inspect_round_trip() {
+    assert_eq!(
+        eval("\"a\\\"b\\\\\\n\\e\\x01\\x7f#{#x\"`"),
+        [Str!(r#""a\"b\\\n\e\x01\x7F\#{#x""#)]
+    );
+    for string in &[
+        "",
+        "a\"b'c",
+        "\\\n\t\r\x1b\x07\x08\x0c\x0b",
+        "\0\u{1}\u{7f}\u{85}é",
+        "#{#$#@#",
+    ] {
+        let item = Str!(*string);
+        assert_eq!(eval(&item.to_string()), eval(&format!("{}`~", item)));
+        assert_eq!(eval(&item.to_string()), [item], "{:?}", string);
+    }
+}
+
+// test assignment to any token
+#[test]
+fn assign_any_token() {
+    for &engine in &[Engine::Tree, Engine::Bytecode] {
+        let eval = |input| eval_engine(input, engine).unwrap();
+        assert_eq!(eval("2:1;1 1+"), [Num!(4)]);
+        assert_eq!(eval("5:-1;-1 01"), [Num!(5), Num!(1)]);
+        assert_eq!(
+            eval("{2+}:1;0 1 [4 5]{1}%"),
+            [Num!(2), Array!([Num!(6), Num!(7)])]
+        );
+        // literals are redefined by their token, not by their value
+        assert_eq!(
+            eval("\"b\":\"a\";'a' \"a\" \"c\""),
+            [Str!("a"), Str!("b"), Str!("c")]
+        );
+        assert_eq!(eval("8:7;7 007 {7}~"), [Num!(8), Num!(7), Num!(8)]);
+        assert_eq!(eval("1:-0;0 -0"), [Num!(0), Num!(1)]);
+        assert_eq!(eval("8:7;{7}{007}+~"), [Num!(8), Num!(7)]);
+
+        // whitespaces and comments run once assigned to
+        assert_eq!(eval("1: ;"), []);
+        assert_eq!(eval("1: ;2 3"), [Num!(2), Num!(1), Num!(3)]);
+        assert_eq!(eval("5:#x\n;#x"), [Num!(5)]);
+        assert_eq!(
+            eval("{1}:\n;1\n{2\n}~"),
+            [Num!(1), Num!(1), Num!(2), Num!(1)]
+        );
+        assert_eq!(eval("7:é;é"), [Num!(7)]);
+        assert_eq!(eval("1:{;{2}~ 3::;"), [Num!(2)]);
+
+        // redefined builtins are used by the predefined functions
+        assert_eq!(eval("{}:print;1 puts"), [Num!(1), Str!("\n")]);
+        assert_eq!(eval("{}:print;{;7}:`;[1]p"), [Num!(7), Str!("\n")]);
+        assert_eq!(eval("{;;;9}:if;0 2 and 1 2 or"), [Num!(9), Num!(9)]);
+        assert_eq!(eval("{;;1}:*;0 0 xor"), [Num!(0), Num!(1)]);
+    }
+
+    let mut it = Interpreter::default();
+    it.exec("1:{;3::;").unwrap();
+    assert_eq!(it.variable("{"), Some(&Num!(1)));
+    assert_eq!(it.variable(":"), Some(&Num!(3)));
+}
+
+// test prelude
+#[test]
+fn prelude() {
+    assert!(golfscript::PRELUDE.contains("{1$if}:and;"));
+
+    let mut it = Interpreter::builder()
+        .prelude(format!("{}{{2*}}:double;1 2", golfscript::PRELUDE))
+        .engine(Engine::Bytecode)
+        .build()
+        .unwrap();
+    assert_eq!(it.exec("3 double 1 0 or").unwrap(), [Num!(6), Num!(1)]);
+    it.exec("{}:double;").unwrap();
+    it.reset();
+    assert_eq!(it.exec("5 double").unwrap(), [Num!(10)]);
+
+    let mut it = Interpreter::builder().no_prelude().build().unwrap();
+    assert_eq!(
+        it.exec("n").unwrap_err().kind,
+        ErrorKind::UndefinedVariable("n".to_string())
+    );
+    assert_eq!(it.variable("puts"), None);
+
+    let err = Interpreter::builder().prelude("1 0/").build().unwrap_err();
+    assert_eq!(err.kind, ErrorKind::DivisionByZero);
+
+    // the prelude runs once, `reset` restores what it defined
+    let out = SharedBuf::default();
+    let mut it = Interpreter::builder()
+        .io(io::empty(), out.clone())
+        .prelude("\"x\"print 1:a;")
+        .build()
+        .unwrap();
+    it.exec("2:a;").unwrap();
+    it.reset();
+    assert_eq!(it.exec("a").unwrap(), [Num!(1)]);
+    assert_eq!(*out.0.borrow(), b"x");
+}
+
+// test markers left open by blocks
+#[test]
+fn markers_after_block_calls() {
+    for &engine in &[Engine::Tree, Engine::Bytecode] {
+        let eval = |input| eval_engine(input, engine).unwrap();
+        assert_eq!(eval("[1]{[0}?]"), [Array!([])]);
+        assert_eq!(eval("[1 2]:a;{[}:f; a{f 0}?]"), [Array!([])]);
+        assert_eq!(
+            eval("[1 2]{[0}%]"),
+            [Array!([Array!([Num!(1), Num!(0), Num!(2), Num!(0)])])]
+        );
+    }
+}